@@ -0,0 +1,266 @@
+//! Keyframe animation of [`Transform`] and [`Shape`] nodes.
+//!
+//! MagicaVoxel stores animations as keyframes. Each entry of
+//! [`Transform::frames`] and each [`ShapeModel`] of [`Shape::models`] is a
+//! keyframe, and its frame number is stored in the `_f` attribute. A keyframe
+//! without `_f` is at frame `0`.
+//!
+//! To evaluate a node at a frame, the keyframes surrounding that frame are
+//! looked up:
+//!
+//!  - Before the first keyframe, the first keyframe is used.
+//!  - After the last keyframe, the last keyframe is used. If the node's `_loop`
+//!    attribute is set, the animation repeats instead, starting again at the
+//!    first keyframe.
+//!  - Between two keyframes, translations are linearly interpolated and rounded
+//!    down. Rotations and models are held until the next keyframe.
+
+use std::convert::TryFrom;
+
+use crate::{
+    scene::SceneTransform,
+    types::{
+        Attributes,
+        Rotation,
+        Shape,
+        ShapeModel,
+        Transform,
+        Vector,
+    },
+};
+
+impl Attributes {
+    /// Returns the frame number of a keyframe (`_f` attribute). Defaults to `0`
+    /// if the attribute is missing or invalid.
+    pub fn frame_index(&self) -> u32 {
+        self.get("_f")
+            .and_then(|f| f.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the `_loop` attribute is set.
+    pub fn is_looped(&self) -> bool {
+        self.get("_loop") == Some("1")
+    }
+}
+
+impl Transform {
+    /// Returns the translation at `frame`. See the [module
+    /// documentation](self) for how keyframes are evaluated.
+    pub fn translation_at(&self, frame: u32) -> Vector<i32> {
+        let keyframes = self.keyframes();
+        let translation = |i: usize| self.get_transform(i).unwrap_or_default();
+
+        match find_keyframes(&keyframes, self.attributes.is_looped(), frame) {
+            None => Vector::default(),
+            Some(Span::At(i)) => translation(keyframes[i].1),
+            Some(Span::Between(i, frame)) => {
+                let (start_frame, start) = keyframes[i];
+                let (end_frame, end) = keyframes[i + 1];
+                let t = i128::from(frame - start_frame);
+                let len = i128::from(end_frame - start_frame);
+
+                let start = translation(start);
+                let end = translation(end);
+                let lerp = |a: i32, b: i32| {
+                    let (a, b) = (i128::from(a), i128::from(b));
+                    i32::try_from(a + ((b - a) * t).div_euclid(len)).expect("int overflow")
+                };
+
+                Vector::new(
+                    lerp(start.x, end.x),
+                    lerp(start.y, end.y),
+                    lerp(start.z, end.z),
+                )
+            }
+        }
+    }
+
+    /// Returns the rotation at `frame`. See the [module
+    /// documentation](self) for how keyframes are evaluated.
+    pub fn rotation_at(&self, frame: u32) -> Rotation {
+        let keyframes = self.keyframes();
+
+        find_keyframes(&keyframes, self.attributes.is_looped(), frame)
+            .and_then(|span| self.get_rotation(keyframes[span.index()].1))
+            .unwrap_or_default()
+    }
+
+    /// Returns the local transform at `frame`.
+    pub fn transform_at(&self, frame: u32) -> SceneTransform {
+        SceneTransform::new(self.translation_at(frame), self.rotation_at(frame))
+    }
+
+    /// Returns `(frame number, index into frames)` sorted by frame number.
+    fn keyframes(&self) -> Vec<(u32, usize)> {
+        let mut keyframes = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(i, attributes)| (attributes.frame_index(), i))
+            .collect::<Vec<_>>();
+        keyframes.sort();
+        keyframes
+    }
+}
+
+impl Shape {
+    /// Returns the model that is displayed at `frame`, or `None` if the shape
+    /// has no models. See the [module documentation](self) for how keyframes
+    /// are evaluated.
+    pub fn model_at(&self, frame: u32) -> Option<&ShapeModel> {
+        let mut keyframes = self
+            .models
+            .iter()
+            .enumerate()
+            .map(|(i, model)| (model.attributes.frame_index(), i))
+            .collect::<Vec<_>>();
+        keyframes.sort();
+
+        let span = find_keyframes(&keyframes, self.attributes.is_looped(), frame)?;
+        Some(&self.models[keyframes[span.index()].1])
+    }
+}
+
+/// Where a frame lies relative to a list of keyframes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Span {
+    /// Use exactly the keyframe with this index.
+    At(usize),
+
+    /// The frame (second field) lies between the keyframe with this index and
+    /// the next one.
+    Between(usize, u32),
+}
+
+impl Span {
+    /// The index of the keyframe at or before the frame.
+    fn index(&self) -> usize {
+        match self {
+            Span::At(i) => *i,
+            Span::Between(i, _) => *i,
+        }
+    }
+}
+
+/// Finds the keyframes around `frame`. `keyframes` must be sorted by frame
+/// number. Returns `None` if there are no keyframes.
+fn find_keyframes<T>(keyframes: &[(u32, T)], looped: bool, mut frame: u32) -> Option<Span> {
+    let first = keyframes.first()?.0;
+    let last = keyframes.last()?.0;
+
+    if frame > last && looped {
+        // The period is computed in `u64`, since it overflows if `last` is
+        // `u32::MAX`.
+        let period = u64::from(last - first) + 1;
+        frame = first + (u64::from(frame - first) % period) as u32;
+    }
+
+    if frame <= first {
+        return Some(Span::At(0));
+    }
+    if frame >= last {
+        return Some(Span::At(keyframes.len() - 1));
+    }
+
+    // `frame` is strictly between the first and last keyframe, so there is a
+    // keyframe after it.
+    let next = keyframes
+        .iter()
+        .position(|(f, _)| *f > frame)
+        .expect("keyframe after frame");
+
+    if keyframes[next - 1].0 == frame {
+        Some(Span::At(next - 1))
+    }
+    else {
+        Some(Span::Between(next - 1, frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{
+        Attributes,
+        Shape,
+        ShapeModel,
+        Transform,
+        Vector,
+    };
+
+    fn attributes(pairs: &[(&str, &str)]) -> Attributes {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn transform(looped: bool) -> Transform {
+        Transform {
+            node_id: 0,
+            attributes: attributes(if looped { &[("_loop", "1")] } else { &[] }),
+            child_node_id: 1,
+            reserved_id: None,
+            layer_id: None,
+            frames: vec![
+                attributes(&[("_f", "10"), ("_t", "10 0 -4"), ("_r", "17")]),
+                attributes(&[("_f", "0"), ("_t", "0 0 0")]),
+            ],
+        }
+    }
+
+    #[test]
+    fn it_interpolates_translations() {
+        let transform = transform(false);
+        assert_eq!(transform.translation_at(0), Vector::new(0, 0, 0));
+        assert_eq!(transform.translation_at(5), Vector::new(5, 0, -2));
+        assert_eq!(transform.translation_at(3), Vector::new(3, 0, -2));
+        assert_eq!(transform.translation_at(10), Vector::new(10, 0, -4));
+        assert_eq!(transform.translation_at(100), Vector::new(10, 0, -4));
+    }
+
+    #[test]
+    fn it_holds_rotations() {
+        let transform = transform(false);
+        assert_eq!(transform.rotation_at(9).to_byte(), 4);
+        assert_eq!(transform.rotation_at(10).to_byte(), 17);
+    }
+
+    #[test]
+    fn it_loops_animations() {
+        let transform = transform(true);
+        assert_eq!(transform.translation_at(11), Vector::new(0, 0, 0));
+        assert_eq!(transform.translation_at(16), Vector::new(5, 0, -2));
+    }
+
+    #[test]
+    fn it_loops_animations_up_to_the_last_frame() {
+        let mut transform = transform(true);
+        transform.frames[0] = attributes(&[("_f", "4294967295"), ("_t", "10 0 0")]);
+        assert_eq!(transform.translation_at(0), Vector::new(0, 0, 0));
+        assert_eq!(transform.translation_at(u32::MAX / 2), Vector::new(4, 0, 0));
+        assert_eq!(transform.translation_at(u32::MAX), Vector::new(10, 0, 0));
+    }
+
+    #[test]
+    fn it_selects_shape_models() {
+        let shape = Shape {
+            node_id: 0,
+            attributes: Attributes::default(),
+            models: vec![
+                ShapeModel {
+                    model_id: 0,
+                    attributes: Attributes::default(),
+                },
+                ShapeModel {
+                    model_id: 1,
+                    attributes: attributes(&[("_f", "4")]),
+                },
+            ],
+        };
+
+        assert_eq!(shape.model_at(3).unwrap().model_id, 0);
+        assert_eq!(shape.model_at(4).unwrap().model_id, 1);
+        assert_eq!(shape.model_at(9).unwrap().model_id, 1);
+    }
+}
@@ -20,7 +20,6 @@ use std::{
         Write,
     },
     str::FromStr,
-    u64,
 };
 
 use byteorder::{
@@ -28,11 +27,19 @@ use byteorder::{
     WriteBytesExt,
     LE,
 };
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use thiserror::Error;
 
 use crate::{
     reader::Error as ReadError,
-    types::Version,
+    types::{
+        Version,
+        VersionPolicy,
+    },
     writer::Error as WriteError,
 };
 
@@ -46,6 +53,7 @@ pub struct ChunkIdParseError(String);
 /// The best way to find out which chunk IDs MagicaVoxel uses, is by looking at
 /// its [source](https://github.com/aiekick/MagicaVoxel_File_Writer/blob/master/VoxWriter.cpp)
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum ChunkId {
     // These are actually defined in the spec.
     Main,
@@ -140,7 +148,7 @@ impl FromStr for ChunkId {
     type Err = ChunkIdParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.as_bytes().len() != 4 {
+        if s.len() != 4 {
             return Err(ChunkIdParseError(s.to_owned()));
         }
 
@@ -199,10 +207,18 @@ impl Chunk {
         })
     }
 
+    /// Reads its raw contents.
     pub fn read_content_to_vec<R: Read + Seek>(&self, reader: R) -> Result<Vec<u8>, ReadError> {
-        let mut buf = vec![];
-        self.content(reader)?.read_to_end(&mut buf)?;
-        Ok(buf)
+        read_exact_to_vec(self.content(reader)?, self.content_len)
+    }
+
+    /// Reads the raw data of all its children, including their headers.
+    pub fn read_children_to_vec<R: Read + Seek>(
+        &self,
+        mut reader: R,
+    ) -> Result<Vec<u8>, ReadError> {
+        reader.seek(SeekFrom::Start(self.children_offset().into()))?;
+        read_exact_to_vec(reader, self.children_len)
     }
 
     /// Creates an iterator over its children. The iterator yields
@@ -273,6 +289,17 @@ impl Chunk {
     }
 }
 
+/// Reads exactly `len` bytes. The length comes from the file, so the buffer
+/// only grows with the bytes that are actually there.
+fn read_exact_to_vec<R: Read>(reader: R, len: u32) -> Result<Vec<u8>, ReadError> {
+    let mut buf = vec![];
+    reader.take(len.into()).read_to_end(&mut buf)?;
+    if buf.len() != len as usize {
+        return Err(IoError::from(ErrorKind::UnexpectedEof).into());
+    }
+    Ok(buf)
+}
+
 /// A reader for a chunk's contents.
 pub struct ContentReader<R> {
     reader: R,
@@ -362,7 +389,17 @@ pub fn read_chunk_at<R: Read + Seek>(mut reader: R, offset: &mut u32) -> Result<
 }
 
 /// Reads the VOX file's header, verifies it, and then reads the MAIN chunk.
-pub fn read_main_chunk<R: Read + Seek>(mut reader: R) -> Result<(Chunk, Version), ReadError> {
+/// Only [`Version::KNOWN`] versions are accepted.
+pub fn read_main_chunk<R: Read + Seek>(reader: R) -> Result<(Chunk, Version), ReadError> {
+    read_main_chunk_with_policy(reader, VersionPolicy::Strict)
+}
+
+/// Same as [`read_main_chunk`], but lets you specify which versions are
+/// accepted.
+pub fn read_main_chunk_with_policy<R: Read + Seek>(
+    mut reader: R,
+    version_policy: VersionPolicy,
+) -> Result<(Chunk, Version), ReadError> {
     let mut buf = [0u8; 4];
     reader.read_exact(&mut buf)?;
     log::trace!("magic = {:?}", buf);
@@ -372,7 +409,7 @@ pub fn read_main_chunk<R: Read + Seek>(mut reader: R) -> Result<(Chunk, Version)
 
     let version = Version::read(&mut reader)?;
     log::trace!("version = {:?}", version);
-    if !version.is_supported() {
+    if !version.is_accepted_by(version_policy) {
         return Err(ReadError::UnsupportedFileVersion { version });
     }
 
@@ -551,7 +588,7 @@ impl<W: Write + Seek> ChunkWriter<W> {
             self.children_len
         );
 
-        let old_pos = self.writer.seek(SeekFrom::Current(0))?;
+        let old_pos = self.writer.stream_position()?;
         self.writer.seek(SeekFrom::Start(self.offset + 4))?;
 
         self.writer.write_u32::<LE>(self.content_len)?;
@@ -660,6 +697,130 @@ pub fn chunk_writer<W: Write + Seek, F: FnMut(&mut ChunkWriter<W>) -> Result<(),
     Ok(())
 }
 
+/// Writes a chunk to a writer that doesn't need to implement `Seek`.
+///
+/// Unlike [`chunk_writer`], this needs to know the length of the content and
+/// children before writing the chunk header. Therefore `content` and
+/// `children` are each called twice: First with a [`LengthCounter`] to compute
+/// the lengths, and then with the actual writer. They must write the same data
+/// each time they're called.
+///
+/// Since nested calls multiply the number of times the innermost closures are
+/// called, you should use [`write_chunk_header`] instead, if you can compute
+/// the lengths directly.
+///
+/// # Example
+///
+/// ```
+/// # use vox_format::chunk::*;
+/// let mut buf = vec![];
+/// write_chunk(
+///     &mut buf,
+///     ChunkId::Main,
+///     |_| Ok(()),
+///     |writer| {
+///         write_content_chunk(writer, ChunkId::Note, |writer| {
+///             writer.write_all(b"Hello World")?;
+///             Ok(())
+///         })
+///     },
+/// )
+/// .unwrap();
+/// assert_eq!(buf.len(), 12 + 12 + 11);
+/// ```
+pub fn write_chunk<W, C, D>(
+    mut writer: W,
+    chunk_id: ChunkId,
+    mut content: C,
+    mut children: D,
+) -> Result<(), WriteError>
+where
+    W: Write,
+    C: FnMut(&mut dyn Write) -> Result<(), WriteError>,
+    D: FnMut(&mut dyn Write) -> Result<(), WriteError>,
+{
+    let content_len = LengthCounter::count(&mut content)?;
+    let children_len = LengthCounter::count(&mut children)?;
+
+    write_chunk_header(&mut writer, chunk_id, content_len, children_len)?;
+
+    content(&mut writer)?;
+    children(&mut writer)?;
+
+    Ok(())
+}
+
+/// Writes a chunk header with known lengths. The content and children must be
+/// written right after it.
+pub fn write_chunk_header<W: Write>(
+    mut writer: W,
+    chunk_id: ChunkId,
+    content_len: u32,
+    children_len: u32,
+) -> Result<(), WriteError> {
+    log::trace!(
+        "Write chunk {:?}: content_len = {}, children_len = {}",
+        chunk_id,
+        content_len,
+        children_len
+    );
+
+    chunk_id.write(&mut writer)?;
+    writer.write_u32::<LE>(content_len)?;
+    writer.write_u32::<LE>(children_len)?;
+
+    Ok(())
+}
+
+/// Short-hand for [`write_chunk`] for chunks that only have content data.
+pub fn write_content_chunk<W, C>(writer: W, chunk_id: ChunkId, content: C) -> Result<(), WriteError>
+where
+    W: Write,
+    C: FnMut(&mut dyn Write) -> Result<(), WriteError>,
+{
+    write_chunk(writer, chunk_id, content, |_| Ok(()))
+}
+
+/// A writer that discards all data, but counts the number of bytes written to
+/// it.
+#[derive(Clone, Debug, Default)]
+pub struct LengthCounter {
+    len: u64,
+}
+
+impl LengthCounter {
+    /// Returns the number of bytes written so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if nothing was written yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes that `f` writes. Fails if it doesn't fit
+    /// into a chunk length.
+    pub fn count<F: FnMut(&mut dyn Write) -> Result<(), WriteError>>(
+        mut f: F,
+    ) -> Result<u32, WriteError> {
+        let mut counter = Self::default();
+        f(&mut counter)?;
+        Ok(counter.len.try_into()?)
+    }
+}
+
+impl Write for LengthCounter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
+
 #[derive(Debug, Error)]
 #[error("The argument {pos:?} to seek is invalid.")]
 struct InvalidSeek {
@@ -682,14 +843,11 @@ fn seek_to(current: u32, start: u32, end: u32, pos: SeekFrom) -> Result<u32, IoE
         .and_then(|p| p.try_into().ok());
 
     new_pos.ok_or_else(|| {
-        IoError::new(
-            ErrorKind::Other,
-            InvalidSeek {
-                current,
-                start,
-                end,
-                pos,
-            },
-        )
+        IoError::other(InvalidSeek {
+            current,
+            start,
+            end,
+            pos,
+        })
     })
 }
@@ -0,0 +1,238 @@
+//! Connected components of models.
+//!
+//! Voxels are connected if they are neighbors according to a
+//! [`Connectivity`]. [`components`] labels the connected components of a
+//! model, and [`split`] splits a model into one model per component. This is
+//! useful to find floating voxels, or to separate a model into its props.
+
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    edit::{
+        model_to_point,
+        point_to_model,
+        EditModel,
+        Part,
+    },
+    types::{
+        ColorIndex,
+        Vector,
+        Voxel,
+    },
+};
+
+/// Which voxels are neighbors.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Connectivity {
+    /// Voxels that share a face.
+    #[default]
+    Six,
+
+    /// Voxels that share a face or an edge.
+    Eighteen,
+
+    /// Voxels that share a face, an edge or a corner.
+    TwentySix,
+}
+
+impl Connectivity {
+    /// Returns the offsets to all neighbors of a voxel.
+    pub fn neighbors(&self) -> impl Iterator<Item = Vector<i32>> {
+        // Number of non-zero components of the offsets.
+        let max_distance = match self {
+            Self::Six => 1,
+            Self::Eighteen => 2,
+            Self::TwentySix => 3,
+        };
+
+        (-1..=1)
+            .flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| Vector::new(x, y, z))))
+            .filter(move |v: &Vector<i32>| {
+                let distance = v.x.abs() + v.y.abs() + v.z.abs();
+                distance > 0 && distance <= max_distance
+            })
+    }
+}
+
+/// Returns the connected components of a model. The index of a component is
+/// its label. Components are ordered by their first voxel in
+/// [`crate::data::VoxModelSource::voxels`], and their voxels are in the order
+/// in which they were reached.
+pub fn components<M: EditModel>(model: &M, connectivity: Connectivity) -> Vec<Vec<Voxel>> {
+    let neighbors = connectivity.neighbors().collect::<Vec<_>>();
+
+    let mut unvisited = model
+        .voxels()
+        .map(|voxel| (point_to_model(voxel.point), voxel.color_index))
+        .collect::<HashMap<_, _>>();
+
+    let mut components = vec![];
+    let mut queue = VecDeque::new();
+
+    for voxel in model.voxels() {
+        let point = point_to_model(voxel.point);
+        let color_index = match unvisited.remove(&point) {
+            Some(color_index) => color_index,
+            None => continue,
+        };
+
+        let mut component = vec![];
+        queue.push_back((point, color_index));
+
+        while let Some((point, color_index)) = queue.pop_front() {
+            component.push(Voxel {
+                point: model_to_point(point),
+                color_index,
+            });
+
+            for offset in &neighbors {
+                let neighbor = point + *offset;
+                if let Some(color_index) = unvisited.remove(&neighbor) {
+                    queue.push_back((neighbor, color_index));
+                }
+            }
+        }
+
+        components.push(component);
+    }
+
+    components
+}
+
+/// Splits a model into one model per connected component. Each model has the
+/// tight size of its component, and is placed at the component's position in
+/// the original model.
+pub fn split<M: EditModel>(model: &M, connectivity: Connectivity) -> Vec<Part<M>> {
+    components(model, connectivity)
+        .into_iter()
+        .map(|voxels| part_from_voxels(&voxels))
+        .collect()
+}
+
+/// Creates a model with a tight size from voxels.
+fn part_from_voxels<M: EditModel>(voxels: &[Voxel]) -> Part<M> {
+    let points = || voxels.iter().map(|voxel| point_to_model(voxel.point));
+    let min = points()
+        .reduce(|a, b| Vector::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)))
+        .unwrap_or_default();
+    let max = points()
+        .reduce(|a, b| Vector::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)))
+        .unwrap_or_default();
+
+    let mut model = M::new((max - min).map(|x| x as u32 + 1));
+    model.set_all(voxels.iter().map(|voxel| {
+        let point = point_to_model(voxel.point) - min;
+        Voxel::new(model_to_point(point), voxel.color_index)
+    }));
+
+    Part { model, offset: min }
+}
+
+/// Removes all voxels whose connected component has fewer than `min_voxels`
+/// voxels, e.g. stray voxels. Returns the number of removed voxels.
+pub fn remove_small_components<M: EditModel>(
+    model: &mut M,
+    connectivity: Connectivity,
+    min_voxels: usize,
+) -> usize {
+    let mut removed = 0;
+    for component in components(model, connectivity) {
+        if component.len() < min_voxels {
+            model.set_all(
+                component
+                    .iter()
+                    .map(|voxel| Voxel::new(voxel.point, ColorIndex(0))),
+            );
+            removed += component.len();
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        components,
+        remove_small_components,
+        split,
+        Connectivity,
+    };
+    use crate::{
+        sparse::SparseModel,
+        types::{
+            ColorIndex,
+            Vector,
+        },
+    };
+
+    fn model() -> SparseModel {
+        let mut model = SparseModel::new(Vector::new(8, 8, 8));
+        // A line along x.
+        model.insert([0, 0, 0], 1);
+        model.insert([1, 0, 0], 1);
+        model.insert([2, 0, 0], 2);
+        // Touches the line at an edge.
+        model.insert([3, 1, 0], 3);
+        // Touches that voxel at a corner.
+        model.insert([4, 2, 1], 4);
+        // Floating.
+        model.insert([7, 7, 7], 5);
+        model
+    }
+
+    #[test]
+    fn it_counts_neighbors() {
+        assert_eq!(Connectivity::Six.neighbors().count(), 6);
+        assert_eq!(Connectivity::Eighteen.neighbors().count(), 18);
+        assert_eq!(Connectivity::TwentySix.neighbors().count(), 26);
+    }
+
+    #[test]
+    fn it_finds_components() {
+        let sizes = |connectivity| {
+            components(&model(), connectivity)
+                .iter()
+                .map(Vec::len)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sizes(Connectivity::Six), vec![3, 1, 1, 1]);
+        assert_eq!(sizes(Connectivity::Eighteen), vec![4, 1, 1]);
+        assert_eq!(sizes(Connectivity::TwentySix), vec![5, 1]);
+    }
+
+    #[test]
+    fn it_splits_models() {
+        let parts = split(&model(), Connectivity::Eighteen);
+        assert_eq!(parts.len(), 3);
+
+        assert_eq!(parts[0].offset, Vector::new(0, 0, 0));
+        assert_eq!(parts[0].model.size(), Vector::new(4, 2, 1));
+        assert_eq!(parts[0].model.get([3, 1, 0]), Some(ColorIndex(3)));
+
+        assert_eq!(parts[1].offset, Vector::new(4, 2, 1));
+        assert_eq!(parts[1].model.size(), Vector::new(1, 1, 1));
+        assert_eq!(parts[1].model.get([0, 0, 0]), Some(ColorIndex(4)));
+    }
+
+    #[test]
+    fn it_removes_small_components() {
+        let mut model = model();
+        assert_eq!(
+            remove_small_components(&mut model, Connectivity::Eighteen, 2),
+            2
+        );
+        assert_eq!(model.num_voxels(), 4);
+        assert_eq!(model.get([7, 7, 7]), None);
+    }
+}
@@ -0,0 +1,253 @@
+//! Boolean operations between models.
+//!
+//! All operations combine a model `a` with a model `b` that is placed at
+//! `offset` relative to `a`. The result has the size of `a`, and voxels of `b`
+//! outside of it are clipped. Use [`crate::transform::pad`] first, if `a`
+//! needs to grow.
+//!
+//! ```
+//! # use vox_format::{csg::{difference, union, Overlap}, dense::DenseModel, edit::EditModel, scene::BoundingBox, types::{ColorIndex, Vector}};
+//! let mut wall = DenseModel::new(Vector::new(8, 1, 8));
+//! wall.fill(BoundingBox::of_size(wall.size()), ColorIndex(1));
+//!
+//! let mut door = DenseModel::new(Vector::new(2, 1, 4));
+//! door.fill(BoundingBox::of_size(door.size()), ColorIndex(2));
+//!
+//! let wall = difference(&wall, &door, Vector::new(3, 0, 0));
+//! assert_eq!(wall.num_voxels(), 64 - 8);
+//! ```
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    edit::{
+        model_to_point,
+        point_to_model,
+        EditModel,
+    },
+    scene::BoundingBox,
+    types::{
+        ColorIndex,
+        Point,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// Which color is kept where both models have a voxel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Overlap {
+    /// Keep the color of `a`.
+    KeepA,
+
+    /// Keep the color of `b`.
+    #[default]
+    KeepB,
+}
+
+/// Returns all voxels that are in `a` or `b`.
+pub fn union<M: EditModel, N: EditModel>(a: &M, b: &N, offset: Vector<i32>, overlap: Overlap) -> M {
+    let mut result = copy(a);
+    result.set_all(changes_in_b(a, b, offset, |point, color_index| {
+        (overlap == Overlap::KeepB || a.get(point).is_none()).then_some(color_index)
+    }));
+    result
+}
+
+/// Returns all voxels that are in both `a` and `b`.
+pub fn intersection<M: EditModel, N: EditModel>(
+    a: &M,
+    b: &N,
+    offset: Vector<i32>,
+    overlap: Overlap,
+) -> M {
+    let mut result = M::new(a.size());
+    result.set_all(changes_in_b(a, b, offset, |point, color_index| {
+        a.get(point).map(|a_color_index| {
+            match overlap {
+                Overlap::KeepA => a_color_index,
+                Overlap::KeepB => color_index,
+            }
+        })
+    }));
+    result
+}
+
+/// Returns all voxels of `a` that are not in `b`.
+pub fn difference<M: EditModel, N: EditModel>(a: &M, b: &N, offset: Vector<i32>) -> M {
+    let mut result = copy(a);
+    result.set_all(changes_in_b(a, b, offset, |_, _| Some(ColorIndex(0))));
+    result
+}
+
+/// Returns all voxels that are in either `a` or `b`, but not in both.
+pub fn xor<M: EditModel, N: EditModel>(a: &M, b: &N, offset: Vector<i32>) -> M {
+    let mut result = copy(a);
+    result.set_all(changes_in_b(a, b, offset, |point, color_index| {
+        if a.get(point).is_some() {
+            Some(ColorIndex(0))
+        }
+        else {
+            Some(color_index)
+        }
+    }));
+    result
+}
+
+fn copy<M: EditModel>(model: &M) -> M {
+    let mut copy = M::new(model.size());
+    copy.set_all(model.voxels());
+    copy
+}
+
+/// Calls `f` for each voxel of `b` that lies within `a`, with its point in
+/// `a`, and collects the colors it returns as changes for
+/// [`EditModel::set_all`]. Color index 0 removes a voxel.
+fn changes_in_b<M: EditModel, N: EditModel, F: FnMut(Point, ColorIndex) -> Option<ColorIndex>>(
+    a: &M,
+    b: &N,
+    offset: Vector<i32>,
+    mut f: F,
+) -> Vec<Voxel> {
+    let bounds = BoundingBox::of_size(a.size().map(|x| x.min(MAX_MODEL_SIZE)));
+
+    b.voxels()
+        .filter_map(|voxel| {
+            let point = point_to_model(voxel.point) + offset;
+            if bounds.contains(point) {
+                let point = model_to_point(point);
+                f(point, voxel.color_index).map(|color_index| Voxel::new(point, color_index))
+            }
+            else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        difference,
+        intersection,
+        union,
+        xor,
+        Overlap,
+    };
+    use crate::{
+        dense::DenseModel,
+        sparse::SparseModel,
+        types::{
+            ColorIndex,
+            Model,
+            Vector,
+            Voxel,
+        },
+    };
+
+    fn a() -> DenseModel {
+        Model {
+            size: Vector::new(3, 1, 1),
+            voxels: vec![Voxel::new([0, 0, 0], 1), Voxel::new([1, 0, 0], 1)],
+        }
+        .into()
+    }
+
+    fn b() -> SparseModel {
+        Model {
+            size: Vector::new(3, 1, 1),
+            voxels: vec![
+                Voxel::new([0, 0, 0], 2),
+                Voxel::new([1, 0, 0], 2),
+                Voxel::new([2, 0, 0], 2),
+            ],
+        }
+        .into()
+    }
+
+    fn colors(model: &DenseModel) -> Vec<Option<u8>> {
+        (0..3)
+            .map(|x| model.get([x, 0, 0]).map(|ColorIndex(c)| c))
+            .collect()
+    }
+
+    #[test]
+    fn it_computes_unions() {
+        let offset = Vector::new(1, 0, 0);
+        assert_eq!(
+            colors(&union(&a(), &b(), offset, Overlap::KeepA)),
+            vec![Some(1), Some(1), Some(2)]
+        );
+        assert_eq!(
+            colors(&union(&a(), &b(), offset, Overlap::KeepB)),
+            vec![Some(1), Some(2), Some(2)]
+        );
+    }
+
+    #[test]
+    fn it_computes_intersections() {
+        let offset = Vector::new(1, 0, 0);
+        assert_eq!(
+            colors(&intersection(&a(), &b(), offset, Overlap::KeepA)),
+            vec![None, Some(1), None]
+        );
+        assert_eq!(
+            colors(&intersection(&a(), &b(), offset, Overlap::KeepB)),
+            vec![None, Some(2), None]
+        );
+    }
+
+    #[test]
+    fn it_computes_differences() {
+        assert_eq!(
+            colors(&difference(&a(), &b(), Vector::new(1, 0, 0))),
+            vec![Some(1), None, None]
+        );
+        assert_eq!(
+            colors(&difference(&a(), &b(), Vector::new(-2, 0, 0))),
+            vec![None, Some(1), None]
+        );
+    }
+
+    #[test]
+    fn it_computes_xor() {
+        assert_eq!(
+            colors(&xor(&a(), &b(), Vector::new(1, 0, 0))),
+            vec![Some(1), None, Some(2)]
+        );
+    }
+
+    #[test]
+    fn it_combines_voxel_lists() {
+        let a = Model::from(a());
+        let b = Model::from(b());
+        let offset = Vector::new(1, 0, 0);
+
+        assert_eq!(
+            union(&a, &b, offset, Overlap::KeepB).voxels,
+            vec![
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([1, 0, 0], 2),
+                Voxel::new([2, 0, 0], 2),
+            ]
+        );
+        assert_eq!(
+            intersection(&a, &b, offset, Overlap::KeepA).voxels,
+            vec![Voxel::new([1, 0, 0], 1)]
+        );
+        assert_eq!(
+            difference(&a, &b, offset).voxels,
+            vec![Voxel::new([0, 0, 0], 1)]
+        );
+        assert_eq!(
+            xor(&a, &b, offset).voxels,
+            vec![Voxel::new([0, 0, 0], 1), Voxel::new([2, 0, 0], 2)]
+        );
+    }
+}
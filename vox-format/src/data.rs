@@ -1,5 +1,10 @@
-//! Contains trait for for reading voxel data, and a simple implementation for
-//! it.
+//! Contains traits for reading and writing voxel data, and a simple
+//! implementation for them.
+
+use std::{
+    iter::Copied,
+    slice,
+};
 
 #[cfg(feature = "serialize")]
 use serde::{
@@ -7,12 +12,20 @@ use serde::{
     Serialize,
 };
 
-use crate::types::{
-    Model,
-    Palette,
-    Size,
-    Version,
-    Voxel,
+use crate::{
+    layout::{
+        Layout,
+        LayoutEntry,
+    },
+    scene::Scene,
+    types::{
+        MaterialPalette,
+        Model,
+        Palette,
+        Size,
+        Version,
+        Voxel,
+    },
 };
 
 /// A simple implementation of [`VoxBuffer`] that collects voxels into `Vec`s.
@@ -31,6 +44,22 @@ impl VoxModelBuffer for Model {
     }
 }
 
+impl VoxModelSource for Model {
+    type Voxels<'a> = Copied<slice::Iter<'a, Voxel>>;
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn num_voxels(&self) -> usize {
+        self.voxels.len()
+    }
+
+    fn voxels(&self) -> Self::Voxels<'_> {
+        self.voxels.iter().copied()
+    }
+}
+
 /// A trait for data structures that can be constructed from a VOX file.
 /// [`crate::vox::VoxData`] implements this for convienience, but you can also
 /// implement this for your own voxel model types.
@@ -38,13 +67,19 @@ impl VoxModelBuffer for Model {
 /// These are always called in this order:
 /// 1. `set_version`
 /// 2. `set_palette`
-/// 3. `set_num_models`
-/// 4. `set_model_size`
+/// 3. `set_materials`
+/// 4. `set_num_models`
+/// 5. `set_model_size`
 ///   1. `set_voxel`
+/// 6. `set_scene`
+/// 7. `set_layout`
 ///
 /// `set_model_size` is always called before the voxels from this model are
 /// passed via `set_voxel`. `set_model_size` is called for each model, and
-/// `set_voxel` is called for each voxel in a model.
+/// `set_voxel` is called for each voxel in a model. `set_materials` and
+/// `set_scene` are only called if the file contains materials or a scene graph
+/// respectively. `set_layout` is only called if
+/// [`crate::reader::ReadOptions::record_layout`] is set.
 pub trait VoxBuffer {
     /// Called after the file version was read.
     ///
@@ -65,6 +100,18 @@ pub trait VoxBuffer {
     /// Called when the color palette was read. This will be read before any
     /// calls to [`Self::set_voxel`].
     fn set_palette(&mut self, palette: Palette);
+
+    /// Called when the material palette was read. This will be called after
+    /// [`Self::set_palette`].
+    fn set_materials(&mut self, _materials: MaterialPalette) {}
+
+    /// Called after all models were read, if the file contains a scene graph.
+    fn set_scene(&mut self, _scene: Scene) {}
+
+    /// Called last with the layout of the file's chunks, if
+    /// [`crate::reader::ReadOptions::record_layout`] is set. See
+    /// [`crate::layout`].
+    fn set_layout(&mut self, _layout: Layout) {}
 }
 
 /// Trait for reading a single model.
@@ -73,6 +120,85 @@ pub trait VoxModelBuffer {
     fn set_voxel(&mut self, voxel: Voxel, palette: &Palette);
 }
 
+/// A trait for data structures that can be written to a VOX file. This is the
+/// counterpart to [`VoxBuffer`]. [`VoxData`] implements this, but you can also
+/// implement this for your own voxel data, to write it without copying it into
+/// a [`VoxData`] first.
+///
+/// If you only want to use your own model type, implement [`VoxModelSource`]
+/// for it and use [`VoxModels`].
+pub trait VoxSource {
+    /// Iterator over the voxels of a model.
+    type Voxels<'a>: Iterator<Item = Voxel>
+    where
+        Self: 'a;
+
+    /// The file version to write.
+    fn version(&self) -> Version {
+        Version::default()
+    }
+
+    /// The color palette.
+    fn palette(&self) -> &Palette;
+
+    /// The material palette, if there is one.
+    fn materials(&self) -> Option<&MaterialPalette> {
+        None
+    }
+
+    /// The scene graph, if there is one.
+    fn scene(&self) -> Option<&Scene> {
+        None
+    }
+
+    /// The layout of the chunks, if it should be preserved. See
+    /// [`crate::layout`].
+    fn layout(&self) -> Option<&Layout> {
+        None
+    }
+
+    /// The number of models.
+    fn num_models(&self) -> usize;
+
+    /// The size of the model with index `model_index`.
+    fn model_size(&self, model_index: usize) -> Size;
+
+    /// The number of voxels in the model with index `model_index`. By default
+    /// this counts the voxels returned by [`Self::voxels`], so you might want
+    /// to override it.
+    fn num_voxels(&self, model_index: usize) -> usize {
+        self.voxels(model_index).count()
+    }
+
+    /// Returns an iterator over the voxels of the model with index
+    /// `model_index`.
+    ///
+    /// The writer might call this more than once per model, so it must return
+    /// the same voxels in the same order each time.
+    fn voxels(&self, model_index: usize) -> Self::Voxels<'_>;
+}
+
+/// Trait for writing a single model. This is the counterpart to
+/// [`VoxModelBuffer`].
+pub trait VoxModelSource {
+    /// Iterator over the voxels of this model.
+    type Voxels<'a>: Iterator<Item = Voxel>
+    where
+        Self: 'a;
+
+    /// The size of this model.
+    fn size(&self) -> Size;
+
+    /// The number of voxels in this model.
+    fn num_voxels(&self) -> usize {
+        self.voxels().count()
+    }
+
+    /// Returns an iterator over the voxels of this model. This must return the
+    /// same voxels in the same order each time it's called.
+    fn voxels(&self) -> Self::Voxels<'_>;
+}
+
 /// A [`VoxBuffer`] implementation that collects the models into a `Vec` and is
 /// generic over the kind of voxel data.
 #[derive(Debug)]
@@ -81,6 +207,12 @@ pub struct VoxModels<V> {
     pub version: Version,
     pub models: Vec<V>,
     pub palette: Palette,
+    pub materials: MaterialPalette,
+    pub scene: Scene,
+
+    /// The layout of the chunks in the file this was read from. This is empty
+    /// unless it was read with [`crate::reader::ReadOptions::record_layout`].
+    pub layout: Layout,
 }
 
 impl<V> Default for VoxModels<V> {
@@ -89,10 +221,34 @@ impl<V> Default for VoxModels<V> {
             version: Version::default(),
             models: vec![],
             palette: Palette::default(),
+            materials: MaterialPalette::default(),
+            scene: Scene::default(),
+            layout: Layout::default(),
         }
     }
 }
 
+impl<V> VoxModels<V> {
+    /// Upgrades data from a legacy file without a scene graph to the modern
+    /// layout. This creates a scene graph with a default transform for each
+    /// model (see [`Scene::from_models`]), and removes the `PACK` chunk from
+    /// the layout.
+    ///
+    /// Returns `false` and does nothing, if there already is a scene graph.
+    pub fn upgrade(&mut self) -> bool {
+        if !self.scene.is_empty() {
+            return false;
+        }
+
+        self.scene = Scene::from_models(self.models.len());
+        self.layout
+            .entries
+            .retain(|entry| *entry != LayoutEntry::Pack);
+
+        true
+    }
+}
+
 impl<V: VoxModelBuffer> VoxBuffer for VoxModels<V> {
     fn set_version(&mut self, version: Version) {
         self.version = version;
@@ -114,4 +270,59 @@ impl<V: VoxModelBuffer> VoxBuffer for VoxModels<V> {
     fn set_palette(&mut self, palette: Palette) {
         self.palette = palette;
     }
+
+    fn set_materials(&mut self, materials: MaterialPalette) {
+        self.materials = materials;
+    }
+
+    fn set_scene(&mut self, scene: Scene) {
+        self.scene = scene;
+    }
+
+    fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+}
+
+impl<V: VoxModelSource> VoxSource for VoxModels<V> {
+    type Voxels<'a>
+        = V::Voxels<'a>
+    where
+        V: 'a;
+
+    fn version(&self) -> Version {
+        self.version
+    }
+
+    fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    fn materials(&self) -> Option<&MaterialPalette> {
+        Some(&self.materials)
+    }
+
+    fn scene(&self) -> Option<&Scene> {
+        Some(&self.scene)
+    }
+
+    fn layout(&self) -> Option<&Layout> {
+        Some(&self.layout)
+    }
+
+    fn num_models(&self) -> usize {
+        self.models.len()
+    }
+
+    fn model_size(&self, model_index: usize) -> Size {
+        self.models[model_index].size()
+    }
+
+    fn num_voxels(&self, model_index: usize) -> usize {
+        self.models[model_index].num_voxels()
+    }
+
+    fn voxels(&self, model_index: usize) -> Self::Voxels<'_> {
+        self.models[model_index].voxels()
+    }
 }
@@ -0,0 +1,344 @@
+//! A dense voxel grid, that supports efficient point-queries.
+//!
+//! [`DenseModel`] stores a color index for every cell of the model, so it's
+//! best suited for models that are mostly filled. Use it with
+//! [`crate::data::VoxModels`] to read VOX files into dense models:
+//!
+//! ```
+//! # let path = "../test_files/test_single_model_default_palette.vox";
+//! use vox_format::{
+//!     data::VoxModels,
+//!     dense::DenseModel,
+//!     reader::read_vox_into,
+//! };
+//!
+//! let mut vox = VoxModels::<DenseModel>::default();
+//! read_vox_into(std::fs::File::open(path).unwrap(), &mut vox).unwrap();
+//! println!("{:?}", vox.models[0].get([0, 0, 0]));
+//! ```
+
+use std::iter::Enumerate;
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    data::{
+        VoxModelBuffer,
+        VoxModelSource,
+    },
+    types::{
+        ColorIndex,
+        Model,
+        Palette,
+        Point,
+        Size,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// A model that stores a color index for each cell in a 3D grid.
+///
+/// Empty cells are stored as color index 0, which is also what MagicaVoxel
+/// uses for empty space. Since points are stored as bytes, at most
+/// [`MAX_MODEL_SIZE`] cells are stored along each axis, even if the model's
+/// size is larger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DenseModel {
+    size: Size,
+    cells: Vec<ColorIndex>,
+    num_voxels: usize,
+}
+
+impl DenseModel {
+    /// Creates an empty model with the given size.
+    pub fn new(size: Size) -> Self {
+        let dims = Self::dims(size);
+        Self {
+            size,
+            cells: vec![ColorIndex(0); (dims.x * dims.y * dims.z) as usize],
+            num_voxels: 0,
+        }
+    }
+
+    /// The size of the model.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The number of filled cells.
+    pub fn num_voxels(&self) -> usize {
+        self.num_voxels
+    }
+
+    /// Returns whether there are no filled cells.
+    pub fn is_empty(&self) -> bool {
+        self.num_voxels == 0
+    }
+
+    /// Returns whether `point` lies within the model.
+    pub fn contains(&self, point: impl Into<Point>) -> bool {
+        self.index(point.into()).is_some()
+    }
+
+    /// Returns the color index at `point`, or `None` if the cell is empty or
+    /// out of bounds.
+    pub fn get(&self, point: impl Into<Point>) -> Option<ColorIndex> {
+        self.index(point.into())
+            .map(|i| self.cells[i])
+            .filter(|color_index| color_index.0 != 0)
+    }
+
+    /// Sets the color index at `point` and returns the previous one. Setting
+    /// color index 0 empties the cell.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `point` is out of bounds.
+    pub fn set(
+        &mut self,
+        point: impl Into<Point>,
+        color_index: impl Into<ColorIndex>,
+    ) -> Option<ColorIndex> {
+        let point = point.into();
+        let i = self
+            .index(point)
+            .unwrap_or_else(|| panic!("Point {:?} out of bounds {:?}", point, self.size));
+
+        let old = std::mem::replace(&mut self.cells[i], color_index.into());
+
+        match (old.0 != 0, self.cells[i].0 != 0) {
+            (false, true) => self.num_voxels += 1,
+            (true, false) => self.num_voxels -= 1,
+            _ => {}
+        }
+
+        Some(old).filter(|color_index| color_index.0 != 0)
+    }
+
+    /// Empties the cell at `point` and returns its color index. Does nothing
+    /// if `point` is out of bounds.
+    pub fn remove(&mut self, point: impl Into<Point>) -> Option<ColorIndex> {
+        let point = point.into();
+        if self.index(point).is_some() {
+            self.set(point, 0)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Empties all cells.
+    pub fn clear(&mut self) {
+        self.cells.iter_mut().for_each(|cell| *cell = ColorIndex(0));
+        self.num_voxels = 0;
+    }
+
+    /// Returns an iterator over all filled cells. The voxels are ordered by z,
+    /// then y, then x coordinate.
+    pub fn voxels(&self) -> DenseVoxels<'_> {
+        DenseVoxels {
+            dims: Self::dims(self.size),
+            cells: self.cells.iter().enumerate(),
+        }
+    }
+
+    fn dims(size: Size) -> Size {
+        size.map(|n| n.min(MAX_MODEL_SIZE))
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let dims = Self::dims(self.size);
+        // Points are stored as unsigned bytes.
+        let point = point.map(|p| u32::from(p as u8));
+
+        (point.x < dims.x && point.y < dims.y && point.z < dims.z)
+            .then(|| (point.x + dims.x * (point.y + dims.y * point.z)) as usize)
+    }
+}
+
+/// Iterator over the filled cells of a [`DenseModel`].
+#[derive(Clone, Debug)]
+pub struct DenseVoxels<'a> {
+    dims: Size,
+    cells: Enumerate<std::slice::Iter<'a, ColorIndex>>,
+}
+
+impl<'a> Iterator for DenseVoxels<'a> {
+    type Item = Voxel;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dims = self.dims;
+        self.cells
+            .find(|(_, color_index)| color_index.0 != 0)
+            .map(|(i, color_index)| {
+                let i = i as u32;
+                let x = i % dims.x;
+                let y = (i / dims.x) % dims.y;
+                let z = i / (dims.x * dims.y);
+                Voxel::new([x as u8 as i8, y as u8 as i8, z as u8 as i8], *color_index)
+            })
+    }
+}
+
+impl VoxModelBuffer for DenseModel {
+    fn new(size: Size) -> Self {
+        DenseModel::new(size)
+    }
+
+    fn set_voxel(&mut self, voxel: Voxel, _palette: &Palette) {
+        if self.contains(voxel.point) {
+            self.set(voxel.point, voxel.color_index);
+        }
+        else {
+            log::warn!("Ignoring voxel out of bounds: {:?}", voxel);
+        }
+    }
+}
+
+impl VoxModelSource for DenseModel {
+    type Voxels<'a> = DenseVoxels<'a>;
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn num_voxels(&self) -> usize {
+        self.num_voxels
+    }
+
+    fn voxels(&self) -> Self::Voxels<'_> {
+        DenseModel::voxels(self)
+    }
+}
+
+/// Converts a [`Model`] into a dense model. Voxels that are out of bounds are
+/// ignored, and later voxels replace earlier ones at the same point.
+impl From<&Model> for DenseModel {
+    fn from(model: &Model) -> Self {
+        let mut dense = DenseModel::new(model.size);
+        for voxel in &model.voxels {
+            if dense.contains(voxel.point) {
+                dense.set(voxel.point, voxel.color_index);
+            }
+        }
+        dense
+    }
+}
+
+impl From<Model> for DenseModel {
+    fn from(model: Model) -> Self {
+        Self::from(&model)
+    }
+}
+
+/// Converts a dense model into a [`Model`], with the voxels in the order of
+/// [`DenseModel::voxels`].
+impl From<&DenseModel> for Model {
+    fn from(dense: &DenseModel) -> Self {
+        Model {
+            size: dense.size,
+            voxels: dense.voxels().collect(),
+        }
+    }
+}
+
+impl From<DenseModel> for Model {
+    fn from(dense: DenseModel) -> Self {
+        Self::from(&dense)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::DenseModel;
+    use crate::{
+        data::VoxModels,
+        reader::{
+            from_slice,
+            read_vox_into,
+        },
+        types::{
+            ColorIndex,
+            Model,
+            Vector,
+            Voxel,
+        },
+        writer::to_vec,
+    };
+
+    #[test]
+    fn it_gets_and_sets_voxels() {
+        let mut model = DenseModel::new(Vector::new(2, 3, 4));
+        assert!(model.is_empty());
+
+        assert_eq!(model.set([1, 2, 3], 5), None);
+        assert_eq!(model.set([1, 2, 3], 6), Some(ColorIndex(5)));
+        assert_eq!(model.set([0, 1, 0], 7), None);
+        assert_eq!(model.num_voxels(), 2);
+
+        assert_eq!(model.get([1, 2, 3]), Some(ColorIndex(6)));
+        assert_eq!(model.get([0, 0, 0]), None);
+        assert_eq!(model.get([2, 0, 0]), None);
+        assert!(!model.contains([-1, 0, 0]));
+
+        assert_eq!(
+            model.voxels().collect::<Vec<_>>(),
+            vec![Voxel::new([0, 1, 0], 7), Voxel::new([1, 2, 3], 6)]
+        );
+
+        assert_eq!(model.remove([0, 1, 0]), Some(ColorIndex(7)));
+        assert_eq!(model.remove([5, 5, 5]), None);
+        assert_eq!(model.num_voxels(), 1);
+    }
+
+    #[test]
+    fn it_converts_models() {
+        let model = Model {
+            size: Vector::new(256, 1, 2),
+            voxels: vec![Voxel::new([-1, 0, 1], 1), Voxel::new([3, 0, 0], 2)],
+        };
+
+        let dense = DenseModel::from(&model);
+        assert_eq!(dense.get([-1, 0, 1]), Some(ColorIndex(1)));
+
+        let converted = Model::from(&dense);
+        assert_eq!(converted.size, model.size);
+        assert_eq!(
+            converted.voxels,
+            vec![Voxel::new([3, 0, 0], 2), Voxel::new([-1, 0, 1], 1)]
+        );
+    }
+
+    #[test]
+    fn it_reads_and_writes_dense_models() {
+        let data = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_multiple_models.vox"
+        ));
+
+        let mut vox = VoxModels::<DenseModel>::default();
+        read_vox_into(Cursor::new(&data[..]), &mut vox).unwrap();
+
+        let expected = from_slice(data).unwrap();
+        assert_eq!(vox.models.len(), expected.models.len());
+        for (dense, model) in vox.models.iter().zip(&expected.models) {
+            assert_eq!(dense.num_voxels(), model.voxels.len());
+            for voxel in &model.voxels {
+                assert_eq!(dense.get(voxel.point), Some(voxel.color_index));
+            }
+        }
+
+        let written = from_slice(&to_vec(&vox).unwrap()).unwrap();
+        for (dense, model) in vox.models.iter().zip(&written.models) {
+            assert_eq!(&DenseModel::from(model), dense);
+        }
+    }
+}
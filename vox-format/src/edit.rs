@@ -0,0 +1,437 @@
+//! Editing of voxel models.
+//!
+//! [`EditModel`] provides editing operations for all model types in this
+//! crate, i.e. [`Model`], [`DenseModel`], [`SparseModel`] and
+//! [`OctreeModel`]. Implement [`EditModel::get`] and [`EditModel::set`] for
+//! your own model type to get the rest.
+//!
+//! [`Model`] searches its voxels on every [`EditModel::get`] and
+//! [`EditModel::set`]. Bulk operations go through [`EditModel::set_all`], which
+//! indexes the voxels once, but for many random accesses, convert the model to
+//! a [`DenseModel`] or [`SparseModel`] first.
+//!
+//! Regions are given as [`BoundingBox`]es in model coordinates. Since points
+//! are stored as bytes, these range from 0 to 255.
+
+use std::collections::HashMap;
+
+use crate::{
+    data::{
+        VoxModelBuffer,
+        VoxModelSource,
+    },
+    dense::DenseModel,
+    octree::OctreeModel,
+    scene::BoundingBox,
+    sparse::SparseModel,
+    types::{
+        ColorIndex,
+        Model,
+        Point,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// Converts a point to model coordinates.
+pub(crate) fn point_to_model(point: Point) -> Vector<i32> {
+    // Points are stored as unsigned bytes.
+    point.map(|p| i32::from(p as u8))
+}
+
+/// Converts model coordinates to a point. The coordinates must be from 0 to
+/// 255.
+pub(crate) fn model_to_point(point: Vector<i32>) -> Point {
+    point.map(|p| p as u8 as i8)
+}
+
+/// A model that is placed at an offset, e.g. because it's a part of a larger
+/// model.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Part<M> {
+    /// The model.
+    pub model: M,
+
+    /// The position of the model's origin.
+    pub offset: Vector<i32>,
+}
+
+/// Editing operations for voxel models.
+pub trait EditModel: VoxModelSource + VoxModelBuffer + Sized {
+    /// Returns the color index at `point`, or `None` if there is no voxel.
+    fn get(&self, point: Point) -> Option<ColorIndex>;
+
+    /// Sets or replaces the voxel at `point` and returns the previous color
+    /// index. Setting color index 0 removes the voxel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `point` is out of bounds.
+    fn set(&mut self, point: Point, color_index: ColorIndex) -> Option<ColorIndex>;
+
+    /// Sets or replaces all `voxels`, like calling [`EditModel::set`] for each
+    /// of them. All bulk operations use this, so model types with a slow `set`
+    /// should override it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a point is out of bounds.
+    fn set_all<I: IntoIterator<Item = Voxel>>(&mut self, voxels: I) {
+        for voxel in voxels {
+            self.set(voxel.point, voxel.color_index);
+        }
+    }
+
+    /// Returns whether `point` lies within the model. Models larger than
+    /// [`MAX_MODEL_SIZE`] are clipped.
+    fn contains(&self, point: Point) -> bool {
+        BoundingBox::of_size(self.size().map(|x| x.min(MAX_MODEL_SIZE)))
+            .contains(point_to_model(point))
+    }
+
+    /// Removes the voxel at `point` and returns its color index. Does nothing
+    /// if `point` is out of bounds.
+    fn remove(&mut self, point: Point) -> Option<ColorIndex> {
+        if self.contains(point) {
+            self.set(point, ColorIndex(0))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Sets all voxels in `region` to `color_index`. The region is clipped to
+    /// the model, and to [`MAX_MODEL_SIZE`]. Filling with color index 0 clears
+    /// the region.
+    fn fill(&mut self, region: BoundingBox, color_index: ColorIndex) {
+        let bounds = BoundingBox::of_size(self.size().map(|x| x.min(MAX_MODEL_SIZE)));
+        if let Some(region) = region.intersection(&bounds) {
+            self.set_all(
+                region
+                    .points()
+                    .map(|point| Voxel::new(model_to_point(point), color_index)),
+            );
+        }
+    }
+
+    /// Removes all voxels in `region`.
+    fn clear_region(&mut self, region: BoundingBox) {
+        self.fill(region, ColorIndex(0));
+    }
+
+    /// Replaces color index `from` with `to` for all voxels in `region`, or
+    /// in the whole model if `region` is `None`. Returns the number of voxels
+    /// that were changed.
+    fn replace_color(
+        &mut self,
+        from: ColorIndex,
+        to: ColorIndex,
+        region: Option<BoundingBox>,
+    ) -> usize {
+        let points = self
+            .voxels()
+            .filter(|voxel| {
+                voxel.color_index == from
+                    && region.is_none_or(|region| region.contains(point_to_model(voxel.point)))
+            })
+            .map(|voxel| voxel.point)
+            .collect::<Vec<_>>();
+
+        self.set_all(points.iter().map(|point| Voxel::new(*point, to)));
+
+        points.len()
+    }
+
+    /// Returns the smallest box that contains all voxels, or `None` if the
+    /// model is empty.
+    fn bounds(&self) -> Option<BoundingBox> {
+        self.voxels().fold(None, |bounds, voxel| {
+            let point = point_to_model(voxel.point);
+            let next = point + Vector::new(1, 1, 1);
+            Some(match bounds {
+                None => {
+                    BoundingBox {
+                        min: point,
+                        max: next,
+                    }
+                }
+                Some(BoundingBox { min, max }) => {
+                    BoundingBox {
+                        min: Vector::new(
+                            min.x.min(point.x),
+                            min.y.min(point.y),
+                            min.z.min(point.z),
+                        ),
+                        max: Vector::new(max.x.max(next.x), max.y.max(next.y), max.z.max(next.z)),
+                    }
+                }
+            })
+        })
+    }
+
+    /// Shrinks the size to the bounds of the voxels, and shifts them to the
+    /// origin. Returns the offset by which the voxels were moved towards the
+    /// origin, or `None` if the model is empty, in which case it's left
+    /// unchanged.
+    fn shrink_to_fit(&mut self) -> Option<Vector<i32>> {
+        let bounds = self.bounds()?;
+        let size = bounds.size().map(|x| x as u32);
+
+        if bounds.min == Vector::default() && size == self.size() {
+            return Some(bounds.min);
+        }
+
+        let mut model = <Self as VoxModelBuffer>::new(size);
+        model.set_all(self.voxels().map(|voxel| {
+            let point = point_to_model(voxel.point) - bounds.min;
+            Voxel::new(model_to_point(point), voxel.color_index)
+        }));
+        *self = model;
+
+        Some(bounds.min)
+    }
+}
+
+impl EditModel for Model {
+    fn get(&self, point: Point) -> Option<ColorIndex> {
+        self.get_voxel(point).map(|voxel| voxel.color_index)
+    }
+
+    /// Replaced voxels keep their position in [`Model::voxels`], new ones are
+    /// appended.
+    fn set(&mut self, point: Point, color_index: ColorIndex) -> Option<ColorIndex> {
+        if !self.contains(point) {
+            panic!("Point {:?} out of bounds {:?}", point, self.size);
+        }
+
+        let index = self.voxels.iter().position(|voxel| voxel.point == point);
+
+        match (index, color_index.0) {
+            (Some(index), 0) => Some(self.voxels.remove(index).color_index),
+            (Some(index), _) => {
+                Some(std::mem::replace(
+                    &mut self.voxels[index].color_index,
+                    color_index,
+                ))
+            }
+            (None, 0) => None,
+            (None, _) => {
+                self.voxels.push(Voxel { point, color_index });
+                None
+            }
+        }
+    }
+
+    /// [`EditModel::set`] searches the voxels, so this indexes them once.
+    /// Replaced voxels keep their position, new ones are appended in order.
+    fn set_all<I: IntoIterator<Item = Voxel>>(&mut self, voxels: I) {
+        let mut index = HashMap::with_capacity(self.voxels.len());
+        for (i, voxel) in self.voxels.iter().enumerate() {
+            // Like `set`, we use the first voxel, if there are duplicates.
+            index.entry(voxel.point).or_insert(i);
+        }
+        let mut removed = vec![false; self.voxels.len()];
+
+        for Voxel { point, color_index } in voxels {
+            if !self.contains(point) {
+                panic!("Point {:?} out of bounds {:?}", point, self.size);
+            }
+
+            match index.get(&point) {
+                Some(&i) => {
+                    self.voxels[i].color_index = color_index;
+                    removed[i] = color_index.0 == 0;
+                }
+                None if color_index.0 == 0 => {}
+                None => {
+                    index.insert(point, self.voxels.len());
+                    self.voxels.push(Voxel { point, color_index });
+                    removed.push(false);
+                }
+            }
+        }
+
+        let mut removed = removed.into_iter();
+        self.voxels.retain(|_| !removed.next().unwrap_or_default());
+    }
+}
+
+impl EditModel for DenseModel {
+    fn get(&self, point: Point) -> Option<ColorIndex> {
+        DenseModel::get(self, point)
+    }
+
+    fn set(&mut self, point: Point, color_index: ColorIndex) -> Option<ColorIndex> {
+        DenseModel::set(self, point, color_index)
+    }
+}
+
+impl EditModel for SparseModel {
+    fn get(&self, point: Point) -> Option<ColorIndex> {
+        SparseModel::get(self, point)
+    }
+
+    fn set(&mut self, point: Point, color_index: ColorIndex) -> Option<ColorIndex> {
+        self.insert(point, color_index)
+    }
+}
+
+impl EditModel for OctreeModel {
+    fn get(&self, point: Point) -> Option<ColorIndex> {
+        OctreeModel::get(self, point)
+    }
+
+    fn set(&mut self, point: Point, color_index: ColorIndex) -> Option<ColorIndex> {
+        OctreeModel::set(self, point, color_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EditModel;
+    use crate::{
+        dense::DenseModel,
+        octree::OctreeModel,
+        scene::BoundingBox,
+        sparse::SparseModel,
+        types::{
+            ColorIndex,
+            Model,
+            Vector,
+            Voxel,
+        },
+    };
+
+    fn region(min: [i32; 3], max: [i32; 3]) -> BoundingBox {
+        BoundingBox {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    fn edit<M: EditModel>(mut model: M) -> M {
+        assert_eq!(model.set([1, 1, 1].into(), ColorIndex(1)), None);
+        assert_eq!(
+            model.set([1, 1, 1].into(), ColorIndex(2)),
+            Some(ColorIndex(1))
+        );
+        assert_eq!(model.remove([1, 1, 1].into()), Some(ColorIndex(2)));
+        assert_eq!(model.remove([9, 9, 9].into()), None);
+
+        model.fill(region([2, 2, 2], [10, 10, 10]), ColorIndex(3));
+        assert_eq!(model.num_voxels(), 27);
+
+        model.clear_region(region([4, 2, 2], [5, 5, 5]));
+        assert_eq!(model.num_voxels(), 18);
+
+        assert_eq!(
+            model.replace_color(
+                ColorIndex(3),
+                ColorIndex(4),
+                Some(region([0, 0, 0], [3, 5, 5]))
+            ),
+            9
+        );
+        assert_eq!(model.get([2, 4, 4].into()), Some(ColorIndex(4)));
+        assert_eq!(model.get([3, 4, 4].into()), Some(ColorIndex(3)));
+
+        assert_eq!(model.bounds(), Some(region([2, 2, 2], [4, 5, 5])));
+        assert_eq!(model.shrink_to_fit(), Some(Vector::new(2, 2, 2)));
+        assert_eq!(model.size(), Vector::new(2, 3, 3));
+        assert_eq!(model.get([0, 0, 0].into()), Some(ColorIndex(4)));
+        assert_eq!(model.get([1, 0, 0].into()), Some(ColorIndex(3)));
+        assert_eq!(model.get([2, 0, 0].into()), None);
+
+        model
+    }
+
+    #[test]
+    fn it_edits_all_model_types() {
+        let size = Vector::new(5, 5, 5);
+
+        let mut model = edit(Model {
+            size,
+            voxels: vec![],
+        });
+        model.voxels.sort();
+
+        let sorted_voxels = |model: Model| {
+            let mut voxels = model.voxels;
+            voxels.sort();
+            voxels
+        };
+        assert_eq!(
+            sorted_voxels(edit(DenseModel::new(size)).into()),
+            model.voxels
+        );
+        assert_eq!(
+            sorted_voxels(edit(SparseModel::new(size)).into()),
+            model.voxels
+        );
+        assert_eq!(
+            sorted_voxels(edit(OctreeModel::new(size)).into()),
+            model.voxels
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_order_of_replaced_voxels() {
+        let mut model = Model {
+            size: Vector::new(3, 1, 1),
+            voxels: vec![Voxel::new([2, 0, 0], 1), Voxel::new([0, 0, 0], 1)],
+        };
+        model.set([2, 0, 0].into(), ColorIndex(2));
+        model.set([1, 0, 0].into(), ColorIndex(3));
+
+        assert_eq!(
+            model.voxels,
+            vec![
+                Voxel::new([2, 0, 0], 2),
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([1, 0, 0], 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_sets_voxels_of_models_in_bulk() {
+        let mut model = Model {
+            size: Vector::new(4, 1, 1),
+            voxels: vec![Voxel::new([2, 0, 0], 1), Voxel::new([0, 0, 0], 1)],
+        };
+        model.set_all(vec![
+            Voxel::new([3, 0, 0], 2),
+            Voxel::new([2, 0, 0], 0),
+            Voxel::new([0, 0, 0], 3),
+            Voxel::new([1, 0, 0], 0),
+        ]);
+
+        assert_eq!(
+            model.voxels,
+            vec![Voxel::new([0, 0, 0], 3), Voxel::new([3, 0, 0], 2)]
+        );
+    }
+
+    #[test]
+    fn it_clips_fills_to_the_max_model_size() {
+        let mut model = SparseModel::new(Vector::new(300, 1, 1));
+        model.insert([0, 0, 0], 2);
+        assert!(EditModel::contains(&model, [-1, 0, 0].into()));
+
+        // Points past the max model size would wrap around to the start.
+        model.fill(region([256, 0, 0], [300, 1, 1]), ColorIndex(1));
+        assert_eq!(model.num_voxels(), 1);
+        assert_eq!(model.get([0, 0, 0]), Some(ColorIndex(2)));
+
+        model.fill(BoundingBox::of_size(model.size()), ColorIndex(1));
+        assert_eq!(model.num_voxels(), 256);
+    }
+
+    #[test]
+    fn it_does_not_shrink_empty_models() {
+        let mut model = DenseModel::new(Vector::new(4, 4, 4));
+        assert_eq!(model.shrink_to_fit(), None);
+        assert_eq!(model.size(), Vector::new(4, 4, 4));
+    }
+}
@@ -0,0 +1,271 @@
+//! Flood fill and region selection.
+//!
+//! [`select`] selects the connected region around a seed point, that has the
+//! same content as the seed, i.e. either empty space or voxels of the same
+//! color. [`flood_fill`] fills connected empty space, and [`recolor`] changes
+//! the color of a connected region, like a paint bucket.
+
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+
+use crate::{
+    components::Connectivity,
+    edit::{
+        model_to_point,
+        point_to_model,
+        EditModel,
+    },
+    scene::BoundingBox,
+    types::{
+        ColorIndex,
+        Point,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// Options for [`select`], [`flood_fill`] and [`recolor`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FillOptions {
+    /// Which cells are connected.
+    pub connectivity: Connectivity,
+
+    /// Only cells within this region are selected. If `None`, the whole model
+    /// is searched.
+    pub bounds: Option<BoundingBox>,
+}
+
+/// A region selected by [`select`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+    /// The selected points, in the order in which they were reached.
+    pub points: Vec<Point>,
+
+    /// Whether the region doesn't touch the border of the model or the
+    /// bounds. For empty space, this means that it is an enclosed room.
+    pub is_enclosed: bool,
+}
+
+/// Selects the connected region around `seed`, that has the same content as
+/// `seed`. If `seed` is empty, connected empty space is selected, otherwise
+/// connected voxels of the same color. Returns an empty selection if `seed`
+/// is out of bounds.
+pub fn select<M: EditModel>(model: &M, seed: Point, options: &FillOptions) -> Selection {
+    let model_bounds = BoundingBox::of_size(model.size().map(|x| x.min(MAX_MODEL_SIZE)));
+    let bounds = match options.bounds {
+        Some(bounds) => model_bounds.intersection(&bounds),
+        None => Some(model_bounds),
+    };
+
+    let seed = point_to_model(seed);
+    let bounds = match bounds.filter(|bounds| bounds.contains(seed)) {
+        Some(bounds) => bounds,
+        None => return Selection::default(),
+    };
+
+    let voxels = model
+        .voxels()
+        .map(|voxel| (point_to_model(voxel.point), voxel.color_index))
+        .collect::<HashMap<_, _>>();
+    let content = voxels.get(&seed).copied();
+
+    let size = bounds.size().map(|x| x as usize);
+    let index = |point: Vector<i32>| {
+        let p = (point - bounds.min).map(|x| x as usize);
+        p.x + size.x * (p.y + size.y * p.z)
+    };
+    let mut visited = vec![false; size.x * size.y * size.z];
+
+    let neighbors = options.connectivity.neighbors().collect::<Vec<_>>();
+    let mut selection = Selection {
+        points: vec![],
+        is_enclosed: true,
+    };
+    let mut queue = VecDeque::new();
+
+    visited[index(seed)] = true;
+    queue.push_back(seed);
+
+    while let Some(point) = queue.pop_front() {
+        selection.points.push(model_to_point(point));
+
+        for offset in &neighbors {
+            let neighbor = point + *offset;
+
+            if !bounds.contains(neighbor) {
+                selection.is_enclosed = false;
+                continue;
+            }
+
+            let i = index(neighbor);
+            if !visited[i] && voxels.get(&neighbor).copied() == content {
+                visited[i] = true;
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    selection
+}
+
+/// Fills the connected empty space around `seed` with `color_index`. Does
+/// nothing if `seed` is not empty, or `color_index` is 0. Returns the number
+/// of filled cells.
+pub fn flood_fill<M: EditModel>(
+    model: &mut M,
+    seed: Point,
+    color_index: ColorIndex,
+    options: &FillOptions,
+) -> usize {
+    if color_index.0 == 0 || model.get(seed).is_some() {
+        return 0;
+    }
+    fill_selection(model, seed, color_index, options)
+}
+
+/// Changes the color of the connected voxels around `seed` that have the same
+/// color as `seed` to `color_index`. Does nothing if `seed` is empty or
+/// already has `color_index`. Returns the number of changed voxels.
+pub fn recolor<M: EditModel>(
+    model: &mut M,
+    seed: Point,
+    color_index: ColorIndex,
+    options: &FillOptions,
+) -> usize {
+    match model.get(seed) {
+        None => return 0,
+        Some(seed_color_index) if seed_color_index == color_index => return 0,
+        Some(_) => {}
+    }
+    fill_selection(model, seed, color_index, options)
+}
+
+fn fill_selection<M: EditModel>(
+    model: &mut M,
+    seed: Point,
+    color_index: ColorIndex,
+    options: &FillOptions,
+) -> usize {
+    let selection = select(model, seed, options);
+    model.set_all(
+        selection
+            .points
+            .iter()
+            .map(|point| Voxel::new(*point, color_index)),
+    );
+    selection.points.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        flood_fill,
+        recolor,
+        select,
+        FillOptions,
+    };
+    use crate::{
+        components::Connectivity,
+        dense::DenseModel,
+        edit::EditModel,
+        scene::BoundingBox,
+        types::{
+            ColorIndex,
+            Model,
+            Vector,
+        },
+    };
+
+    /// A hollow box with walls of color 1.
+    fn room() -> DenseModel {
+        let mut model = DenseModel::new(Vector::new(5, 5, 5));
+        model.fill(BoundingBox::of_size(model.size()), ColorIndex(1));
+        model.clear_region(BoundingBox {
+            min: Vector::new(1, 1, 1),
+            max: Vector::new(4, 4, 4),
+        });
+        model
+    }
+
+    #[test]
+    fn it_detects_enclosed_rooms() {
+        let mut model = room();
+        let options = FillOptions::default();
+
+        let selection = select(&model, [2, 2, 2].into(), &options);
+        assert_eq!(selection.points.len(), 27);
+        assert!(selection.is_enclosed);
+
+        model.remove([0, 2, 2]);
+        let selection = select(&model, [2, 2, 2].into(), &options);
+        assert_eq!(selection.points.len(), 28);
+        assert!(!selection.is_enclosed);
+    }
+
+    #[test]
+    fn it_fills_empty_space() {
+        let mut model = Model::from(room());
+        let options = FillOptions {
+            bounds: Some(BoundingBox {
+                min: Vector::new(0, 0, 0),
+                max: Vector::new(5, 5, 2),
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            flood_fill(&mut model, [0, 0, 0].into(), ColorIndex(2), &options),
+            0
+        );
+        assert_eq!(
+            flood_fill(&mut model, [2, 2, 1].into(), ColorIndex(0), &options),
+            0
+        );
+        assert_eq!(
+            flood_fill(&mut model, [2, 2, 1].into(), ColorIndex(2), &options),
+            9
+        );
+        assert_eq!(model.get([2, 2, 1].into()), Some(ColorIndex(2)));
+        assert_eq!(model.get([2, 2, 2].into()), None);
+    }
+
+    #[test]
+    fn it_recolors_regions() {
+        let mut model = DenseModel::new(Vector::new(3, 3, 1));
+        model.set([0, 0, 0], 1);
+        model.set([1, 1, 0], 1);
+        model.set([2, 2, 0], 1);
+        model.set([1, 0, 0], 3);
+
+        let mut options = FillOptions::default();
+        assert_eq!(
+            recolor(
+                &mut model.clone(),
+                [0, 0, 0].into(),
+                ColorIndex(2),
+                &options
+            ),
+            1
+        );
+
+        options.connectivity = Connectivity::Eighteen;
+        assert_eq!(
+            recolor(&mut model, [0, 0, 0].into(), ColorIndex(2), &options),
+            3
+        );
+        assert_eq!(model.get([2, 2, 0]), Some(ColorIndex(2)));
+        assert_eq!(model.get([1, 0, 0]), Some(ColorIndex(3)));
+
+        assert_eq!(
+            recolor(&mut model, [0, 1, 0].into(), ColorIndex(2), &options),
+            0
+        );
+        assert_eq!(
+            recolor(&mut model, [0, 0, 0].into(), ColorIndex(2), &options),
+            0
+        );
+    }
+}
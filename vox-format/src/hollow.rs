@@ -0,0 +1,244 @@
+//! Removal of hidden voxels.
+//!
+//! [`hollow`] removes voxels that can't be seen from outside of the model,
+//! because they're enclosed by other opaque voxels. This makes files smaller
+//! and meshing faster, without changing what the model looks like.
+
+use std::collections::VecDeque;
+
+use crate::{
+    edit::EditModel,
+    types::{
+        ColorIndex,
+        MaterialPalette,
+        MaterialType,
+        Palette,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// Options for [`hollow`].
+#[derive(Copy, Clone, Debug)]
+pub struct HollowOptions<'a> {
+    /// The thickness of the shell that is kept, in voxels. With a shell of 1,
+    /// only voxels that are visible are kept.
+    pub shell: u32,
+
+    /// The palette of the model. Colors with an alpha below 255 are
+    /// transparent.
+    pub palette: Option<&'a Palette>,
+
+    /// The materials of the model. Glass and media materials are
+    /// transparent.
+    pub materials: Option<&'a MaterialPalette>,
+}
+
+impl<'a> Default for HollowOptions<'a> {
+    fn default() -> Self {
+        Self {
+            shell: 1,
+            palette: None,
+            materials: None,
+        }
+    }
+}
+
+/// Returns whether voxels with `color_index` are transparent, because their
+/// color has an alpha below 255 or they're made of glass or media.
+pub fn is_transparent(
+    color_index: ColorIndex,
+    palette: Option<&Palette>,
+    materials: Option<&MaterialPalette>,
+) -> bool {
+    let is_translucent = palette.is_some_and(|palette| palette.get(color_index).a < 255);
+
+    let is_glass = materials
+        .and_then(|materials| materials.get(color_index))
+        .is_some_and(|material| {
+            matches!(
+                material.ty(),
+                Some(MaterialType::Glass) | Some(MaterialType::Media)
+            ) && material.weight().is_none_or(|weight| weight > 0.0)
+        });
+
+    is_translucent || is_glass
+}
+
+/// Removes opaque voxels that are further than [`HollowOptions::shell`] voxels
+/// from visible space. Space is visible if it's reachable from outside of the
+/// model through empty cells and transparent voxels. Transparent voxels are
+/// never removed. Returns the number of removed voxels.
+pub fn hollow<M: EditModel>(model: &mut M, options: &HollowOptions) -> usize {
+    let dims = model.size().map(|x| x.min(MAX_MODEL_SIZE) as usize);
+
+    // The grid has a layer of empty cells around the model.
+    let grid = Vector::new(dims.x + 2, dims.y + 2, dims.z + 2);
+    let index = |x: usize, y: usize, z: usize| x + grid.x * (y + grid.y * z);
+
+    let mut opaque = vec![false; grid.x * grid.y * grid.z];
+    let mut voxels = vec![];
+    for voxel in model.voxels() {
+        // Points are stored as unsigned bytes.
+        let point = voxel.point.map(|p| usize::from(p as u8));
+        if point.x < dims.x && point.y < dims.y && point.z < dims.z {
+            let i = index(point.x + 1, point.y + 1, point.z + 1);
+            opaque[i] = !is_transparent(voxel.color_index, options.palette, options.materials);
+            if opaque[i] {
+                voxels.push((voxel.point, i));
+            }
+        }
+    }
+
+    // 0-1 breadth-first search for the number of opaque voxels between each cell
+    // and the outside.
+    let mut distance = vec![u32::MAX; opaque.len()];
+    let mut queue = VecDeque::new();
+    for z in 0..grid.z {
+        for y in 0..grid.y {
+            for x in 0..grid.x {
+                if x == 0
+                    || y == 0
+                    || z == 0
+                    || x == grid.x - 1
+                    || y == grid.y - 1
+                    || z == grid.z - 1
+                {
+                    distance[index(x, y, z)] = 0;
+                    queue.push_back((x, y, z));
+                }
+            }
+        }
+    }
+
+    while let Some((x, y, z)) = queue.pop_front() {
+        let d = distance[index(x, y, z)];
+
+        let neighbors = [
+            (x.wrapping_sub(1), y, z),
+            (x + 1, y, z),
+            (x, y.wrapping_sub(1), z),
+            (x, y + 1, z),
+            (x, y, z.wrapping_sub(1)),
+            (x, y, z + 1),
+        ];
+
+        for (nx, ny, nz) in neighbors {
+            if nx >= grid.x || ny >= grid.y || nz >= grid.z {
+                continue;
+            }
+
+            let i = index(nx, ny, nz);
+            let cost = u32::from(opaque[i]);
+            let next = d + cost;
+
+            if next < distance[i] && next <= options.shell {
+                distance[i] = next;
+                if cost == 0 {
+                    queue.push_front((nx, ny, nz));
+                }
+                else {
+                    queue.push_back((nx, ny, nz));
+                }
+            }
+        }
+    }
+
+    let removed = voxels
+        .into_iter()
+        .filter(|(_, i)| distance[*i] > options.shell)
+        .map(|(point, _)| Voxel::new(point, ColorIndex(0)))
+        .collect::<Vec<_>>();
+    model.set_all(removed.iter().copied());
+    removed.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        hollow,
+        HollowOptions,
+    };
+    use crate::{
+        dense::DenseModel,
+        edit::EditModel,
+        scene::BoundingBox,
+        types::{
+            Color,
+            ColorIndex,
+            Material,
+            MaterialPalette,
+            MaterialType,
+            Model,
+            Palette,
+            Vector,
+        },
+    };
+
+    fn cube(n: u32) -> DenseModel {
+        let mut model = DenseModel::new(Vector::new(n, n, n));
+        model.fill(BoundingBox::of_size(model.size()), ColorIndex(1));
+        model
+    }
+
+    #[test]
+    fn it_removes_hidden_voxels() {
+        let mut model = cube(5);
+        assert_eq!(hollow(&mut model, &HollowOptions::default()), 27);
+        assert_eq!(model.num_voxels(), 125 - 27);
+        assert_eq!(model.get([2, 2, 2]), None);
+        assert_eq!(model.get([0, 2, 2]), Some(ColorIndex(1)));
+    }
+
+    #[test]
+    fn it_keeps_a_shell() {
+        let mut model = cube(7);
+        let options = HollowOptions {
+            shell: 2,
+            ..Default::default()
+        };
+        assert_eq!(hollow(&mut model, &options), 27);
+        assert_eq!(model.get([1, 1, 1]), Some(ColorIndex(1)));
+        assert_eq!(model.get([2, 2, 2]), None);
+    }
+
+    #[test]
+    fn it_ignores_enclosed_cavities() {
+        let mut model = cube(5);
+        model.remove([2, 2, 2]);
+        hollow(&mut model, &HollowOptions::default());
+        assert_eq!(model.num_voxels(), 125 - 27);
+    }
+
+    #[test]
+    fn it_sees_through_transparent_voxels() {
+        let mut model = cube(5);
+        model.set([0, 2, 2], 2);
+
+        let mut palette = Palette::default();
+        palette.colors[2] = Color::new(255, 255, 255, 128);
+        let options = HollowOptions {
+            palette: Some(&palette),
+            ..Default::default()
+        };
+        assert_eq!(hollow(&mut model.clone(), &options), 26);
+
+        let mut materials = MaterialPalette::default();
+        materials.insert(ColorIndex(2), Material::new(MaterialType::Glass));
+        let options = HollowOptions {
+            materials: Some(&materials),
+            ..Default::default()
+        };
+        assert_eq!(hollow(&mut model, &options), 26);
+        assert_eq!(model.get([0, 2, 2]), Some(ColorIndex(2)));
+        assert_eq!(model.get([1, 2, 2]), Some(ColorIndex(1)));
+    }
+
+    #[test]
+    fn it_hollows_models() {
+        let mut model = Model::from(cube(3));
+        assert_eq!(hollow(&mut model, &HollowOptions::default()), 1);
+        assert_eq!(model.voxels.len(), 26);
+    }
+}
@@ -38,10 +38,10 @@ impl Palette {
     ///
     /// # Note
     ///
-    /// VOX palettes have 256 colors, but VOX files only store the last 255
-    /// colors. The first color is always assumed to be fully transparent. Thus
-    /// any color for the first palette entry will be lost once the palette is
-    /// written to file.
+    /// VOX palettes have 256 colors, and MagicaVoxel always assumes the first
+    /// color to be fully transparent. The first color is written to the last,
+    /// otherwise unused, entry of the `RGBA` chunk, so it's preserved, but
+    /// MagicaVoxel ignores it.
     pub fn from_image(image: &RgbaImage) -> Palette {
         let mut colors = [Color::default(); 256];
         image
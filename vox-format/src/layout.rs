@@ -0,0 +1,91 @@
+//! The layout of chunks in a VOX file.
+//!
+//! When a file is read with [`crate::reader::ReadOptions::record_layout`], the
+//! order of its chunks is recorded as a [`Layout`], and chunks that aren't
+//! parsed (e.g. `rOBJ`, `rCAM` or `NOTE`) are kept as [`RawChunk`]s. If
+//! [`crate::writer::WriteOptions::preserve_layout`] is set, the writer uses the
+//! layout to write the chunks in the original order. An unmodified file is then
+//! written back byte-exact.
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    chunk::ChunkId,
+    types::ColorIndex,
+};
+
+/// The order of the chunks in the `MAIN` chunk of a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Layout {
+    pub entries: Vec<LayoutEntry>,
+}
+
+impl Layout {
+    /// Tests if the layout has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns whether the layout contains a `PACK` chunk.
+    pub fn has_pack(&self) -> bool {
+        self.entries.contains(&LayoutEntry::Pack)
+    }
+
+    /// Returns an iterator over all raw chunks.
+    pub fn raw_chunks(&self) -> impl Iterator<Item = &RawChunk> {
+        self.entries.iter().filter_map(|entry| {
+            match entry {
+                LayoutEntry::Raw(chunk) => Some(chunk),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// A chunk in a [`Layout`]. Parsed chunks reference the data they were parsed
+/// into, so that modifications are written. Nodes, layers and materials are
+/// referenced by their IDs, so they're found even if the scene graph was
+/// modified. Entries that don't reference anything are skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum LayoutEntry {
+    /// The `PACK` chunk.
+    Pack,
+
+    /// The `SIZE` chunk of the model with this index.
+    Size(usize),
+
+    /// The `XYZI` chunk of the model with this index.
+    Xyzi(usize),
+
+    /// The `nTRN`, `nGRP` or `nSHP` chunk of the node with this node ID.
+    Node(u32),
+
+    /// The `LAYR` chunk of the layer with this layer ID.
+    Layer(u32),
+
+    /// The `RGBA` chunk.
+    Rgba,
+
+    /// The `MATL` chunk for this color.
+    Material(ColorIndex),
+
+    /// A chunk that isn't parsed.
+    Raw(RawChunk),
+}
+
+/// A chunk that is stored as is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct RawChunk {
+    pub id: ChunkId,
+    pub content: Vec<u8>,
+
+    /// Children data, including the headers of the children.
+    pub children: Vec<u8>,
+}
@@ -55,11 +55,25 @@
 //! [`nalgebra`]: https://docs.rs/nalgebra/0.28.0/nalgebra/index.html
 //! [`palette`]: https://docs.rs/palette/0.6.0/palette/index.html
 
+pub mod animation;
 pub mod chunk;
+pub mod components;
+pub mod csg;
 pub mod data;
 pub mod default_palette;
+pub mod dense;
+pub mod edit;
+pub mod fill;
+pub mod hollow;
+pub mod layout;
+pub mod octree;
 pub mod reader;
+pub mod scale;
+pub mod scene;
+pub mod sparse;
+pub mod transform;
 pub mod types;
+pub mod validate;
 pub mod writer;
 
 pub use crate::{
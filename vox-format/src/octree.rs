@@ -0,0 +1,556 @@
+//! An octree voxel model.
+//!
+//! [`OctreeModel`] subdivides the model into octants until they are uniform, so
+//! large filled or empty regions only take a single node. This makes it well
+//! suited for level-of-detail and raycasting.
+
+use std::mem::size_of;
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    data::{
+        VoxModelBuffer,
+        VoxModelSource,
+    },
+    scene::BoundingBox,
+    types::{
+        ColorIndex,
+        Model,
+        Palette,
+        Point,
+        Size,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// A node of an [`OctreeModel`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum OctreeNode {
+    /// A uniform octant. Color index 0 is empty space.
+    Leaf(ColorIndex),
+
+    /// An octant that is subdivided into 8 children. The index of a child has
+    /// the x offset in bit 0, the y offset in bit 1 and the z offset in bit 2.
+    Branch(Box<[OctreeNode; 8]>),
+}
+
+impl OctreeNode {
+    /// Returns the number of nodes in this subtree, including this node.
+    pub fn num_nodes(&self) -> usize {
+        match self {
+            Self::Leaf(_) => 1,
+            Self::Branch(children) => 1 + children.iter().map(Self::num_nodes).sum::<usize>(),
+        }
+    }
+
+    fn set(&mut self, point: Vector<u32>, half: u32, color_index: ColorIndex) -> ColorIndex {
+        if let Self::Leaf(leaf) = self {
+            if *leaf == color_index {
+                return *leaf;
+            }
+            if half == 0 {
+                return std::mem::replace(leaf, color_index);
+            }
+
+            let leaf = Self::Leaf(*leaf);
+            *self = Self::Branch(Box::new([
+                leaf.clone(),
+                leaf.clone(),
+                leaf.clone(),
+                leaf.clone(),
+                leaf.clone(),
+                leaf.clone(),
+                leaf.clone(),
+                leaf,
+            ]));
+        }
+
+        let children = match self {
+            Self::Branch(children) => children,
+            Self::Leaf(_) => unreachable!(),
+        };
+
+        let old =
+            children[child_index(point, half)].set(point.map(|p| p % half), half / 2, color_index);
+
+        // Merge uniform children.
+        if let Self::Leaf(first) = children[0] {
+            if children.iter().all(|child| *child == Self::Leaf(first)) {
+                *self = Self::Leaf(first);
+            }
+        }
+
+        old
+    }
+}
+
+fn child_index(point: Vector<u32>, half: u32) -> usize {
+    usize::from(point.x >= half)
+        | usize::from(point.y >= half) << 1
+        | usize::from(point.z >= half) << 2
+}
+
+/// A model that stores its voxels in an octree.
+///
+/// The octree is a cube with a power-of-two side length that covers the
+/// model's size. At most [`MAX_MODEL_SIZE`] cells are stored along each
+/// axis, even if the model's size is larger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct OctreeModel {
+    size: Size,
+    side: u32,
+    root: OctreeNode,
+    num_voxels: usize,
+}
+
+impl OctreeModel {
+    /// Creates an empty model with the given size.
+    pub fn new(size: Size) -> Self {
+        let dims = Self::dims(size);
+        let side = dims.x.max(dims.y).max(dims.z).next_power_of_two();
+
+        Self {
+            size,
+            side,
+            root: OctreeNode::Leaf(ColorIndex(0)),
+            num_voxels: 0,
+        }
+    }
+
+    /// The size of the model.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The side length of the cube covered by the octree.
+    pub fn side(&self) -> u32 {
+        self.side
+    }
+
+    /// The root node.
+    pub fn root(&self) -> &OctreeNode {
+        &self.root
+    }
+
+    /// The number of filled cells.
+    pub fn num_voxels(&self) -> usize {
+        self.num_voxels
+    }
+
+    /// Returns whether there are no filled cells.
+    pub fn is_empty(&self) -> bool {
+        self.num_voxels == 0
+    }
+
+    /// Returns the number of nodes in the octree.
+    pub fn num_nodes(&self) -> usize {
+        self.root.num_nodes()
+    }
+
+    /// Returns the approximate number of bytes used by this model, including
+    /// the heap-allocated nodes.
+    pub fn memory_usage(&self) -> usize {
+        let num_branches = (self.num_nodes() - 1) / 8;
+        size_of::<Self>() + num_branches * size_of::<[OctreeNode; 8]>()
+    }
+
+    /// Returns whether `point` lies within the model.
+    pub fn contains(&self, point: impl Into<Point>) -> bool {
+        self.to_cell(point.into()).is_some()
+    }
+
+    /// Returns the color index at `point`, or `None` if the cell is empty or
+    /// out of bounds.
+    pub fn get(&self, point: impl Into<Point>) -> Option<ColorIndex> {
+        let mut point = self.to_cell(point.into())?;
+        let mut half = self.side / 2;
+        let mut node = &self.root;
+
+        loop {
+            match node {
+                OctreeNode::Leaf(color_index) => {
+                    return Some(*color_index).filter(|color_index| color_index.0 != 0)
+                }
+                OctreeNode::Branch(children) => {
+                    node = &children[child_index(point, half)];
+                    point = point.map(|p| p % half);
+                    half /= 2;
+                }
+            }
+        }
+    }
+
+    /// Sets the color index at `point` and returns the previous one. Setting
+    /// color index 0 empties the cell. Octants that become uniform are merged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `point` is out of bounds.
+    pub fn set(
+        &mut self,
+        point: impl Into<Point>,
+        color_index: impl Into<ColorIndex>,
+    ) -> Option<ColorIndex> {
+        let point = point.into();
+        let cell = self
+            .to_cell(point)
+            .unwrap_or_else(|| panic!("Point {:?} out of bounds {:?}", point, self.size));
+        let color_index = color_index.into();
+
+        let old = self.root.set(cell, self.side / 2, color_index);
+
+        match (old.0 != 0, color_index.0 != 0) {
+            (false, true) => self.num_voxels += 1,
+            (true, false) => self.num_voxels -= 1,
+            _ => {}
+        }
+
+        Some(old).filter(|color_index| color_index.0 != 0)
+    }
+
+    /// Empties the cell at `point` and returns its color index. Does nothing
+    /// if `point` is out of bounds.
+    pub fn remove(&mut self, point: impl Into<Point>) -> Option<ColorIndex> {
+        let point = point.into();
+        if self.contains(point) {
+            self.set(point, 0)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Empties all cells.
+    pub fn clear(&mut self) {
+        self.root = OctreeNode::Leaf(ColorIndex(0));
+        self.num_voxels = 0;
+    }
+
+    /// Returns an iterator over all filled cells, in depth-first order of the
+    /// octree.
+    pub fn voxels(&self) -> OctreeVoxels<'_> {
+        self.voxels_in(BoundingBox {
+            min: Vector::default(),
+            max: Self::dims(self.size).map(|n| n as i32),
+        })
+    }
+
+    /// Returns an iterator over the filled cells within `region`, in
+    /// depth-first order of the octree. Octants outside of `region` are
+    /// skipped. The region is in model coordinates, i.e. from 0 to 255.
+    pub fn voxels_in(&self, region: BoundingBox) -> OctreeVoxels<'_> {
+        let dims = Self::dims(self.size);
+        let clip = |min: i32, max: i32, n: u32| {
+            let min = min.max(0) as u32;
+            let max = (max.max(0) as u32).min(n);
+            (min, max.max(min))
+        };
+        let (min_x, max_x) = clip(region.min.x, region.max.x, dims.x);
+        let (min_y, max_y) = clip(region.min.y, region.max.y, dims.y);
+        let (min_z, max_z) = clip(region.min.z, region.max.z, dims.z);
+
+        OctreeVoxels {
+            stack: vec![(&self.root, Vector::default(), self.side)],
+            min: Vector::new(min_x, min_y, min_z),
+            max: Vector::new(max_x, max_y, max_z),
+            leaf: None,
+        }
+    }
+
+    /// Returns whether there are no filled cells within `region`.
+    pub fn is_region_empty(&self, region: BoundingBox) -> bool {
+        self.voxels_in(region).next().is_none()
+    }
+
+    fn dims(size: Size) -> Size {
+        size.map(|n| n.min(MAX_MODEL_SIZE))
+    }
+
+    fn to_cell(&self, point: Point) -> Option<Vector<u32>> {
+        let dims = Self::dims(self.size);
+        // Points are stored as unsigned bytes.
+        let point = point.map(|p| u32::from(p as u8));
+        (point.x < dims.x && point.y < dims.y && point.z < dims.z).then_some(point)
+    }
+}
+
+/// Iterator over the filled cells of an [`OctreeModel`].
+#[derive(Clone, Debug)]
+pub struct OctreeVoxels<'a> {
+    /// Nodes that still need to be visited, with their origin and side length.
+    stack: Vec<(&'a OctreeNode, Vector<u32>, u32)>,
+
+    /// The region to iterate over (`max` is exclusive).
+    min: Vector<u32>,
+    max: Vector<u32>,
+
+    /// The leaf that is being iterated over.
+    leaf: Option<LeafCursor>,
+}
+
+/// The cells of a filled leaf, clipped to the region.
+#[derive(Clone, Debug)]
+struct LeafCursor {
+    color_index: ColorIndex,
+    point: Vector<u32>,
+    min: Vector<u32>,
+    max: Vector<u32>,
+}
+
+impl<'a> Iterator for OctreeVoxels<'a> {
+    type Item = Voxel;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(LeafCursor {
+                color_index,
+                point,
+                min,
+                max,
+            }) = &mut self.leaf
+            {
+                if point.z < max.z {
+                    let voxel = Voxel::new(point.map(|p| p as u8 as i8), *color_index);
+
+                    point.x += 1;
+                    if point.x == max.x {
+                        point.x = min.x;
+                        point.y += 1;
+                        if point.y == max.y {
+                            point.y = min.y;
+                            point.z += 1;
+                        }
+                    }
+
+                    return Some(voxel);
+                }
+                self.leaf = None;
+            }
+
+            let (node, origin, side) = self.stack.pop()?;
+
+            // Clip the octant to the region.
+            let min = Vector::new(
+                origin.x.max(self.min.x),
+                origin.y.max(self.min.y),
+                origin.z.max(self.min.z),
+            );
+            let max = Vector::new(
+                (origin.x + side).min(self.max.x),
+                (origin.y + side).min(self.max.y),
+                (origin.z + side).min(self.max.z),
+            );
+            if min.x >= max.x || min.y >= max.y || min.z >= max.z {
+                continue;
+            }
+
+            match node {
+                OctreeNode::Leaf(color_index) => {
+                    if color_index.0 != 0 {
+                        self.leaf = Some(LeafCursor {
+                            color_index: *color_index,
+                            point: min,
+                            min,
+                            max,
+                        });
+                    }
+                }
+                OctreeNode::Branch(children) => {
+                    let half = side / 2;
+                    for (i, child) in children.iter().enumerate().rev() {
+                        let offset = Vector::new(
+                            (i as u32 & 1) * half,
+                            (i as u32 >> 1 & 1) * half,
+                            (i as u32 >> 2 & 1) * half,
+                        );
+                        self.stack.push((child, origin + offset, half));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl VoxModelBuffer for OctreeModel {
+    fn new(size: Size) -> Self {
+        OctreeModel::new(size)
+    }
+
+    fn set_voxel(&mut self, voxel: Voxel, _palette: &Palette) {
+        if self.contains(voxel.point) {
+            self.set(voxel.point, voxel.color_index);
+        }
+        else {
+            log::warn!("Ignoring voxel out of bounds: {:?}", voxel);
+        }
+    }
+}
+
+impl VoxModelSource for OctreeModel {
+    type Voxels<'a> = OctreeVoxels<'a>;
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn num_voxels(&self) -> usize {
+        self.num_voxels
+    }
+
+    fn voxels(&self) -> Self::Voxels<'_> {
+        OctreeModel::voxels(self)
+    }
+}
+
+/// Converts a [`Model`] into an octree. Voxels that are out of bounds are
+/// ignored, and later voxels replace earlier ones at the same point.
+impl From<&Model> for OctreeModel {
+    fn from(model: &Model) -> Self {
+        let mut octree = OctreeModel::new(model.size);
+        for voxel in &model.voxels {
+            if octree.contains(voxel.point) {
+                octree.set(voxel.point, voxel.color_index);
+            }
+        }
+        octree
+    }
+}
+
+impl From<Model> for OctreeModel {
+    fn from(model: Model) -> Self {
+        Self::from(&model)
+    }
+}
+
+/// Converts an octree into a [`Model`], with the voxels in the order of
+/// [`OctreeModel::voxels`].
+impl From<&OctreeModel> for Model {
+    fn from(octree: &OctreeModel) -> Self {
+        Model {
+            size: octree.size,
+            voxels: octree.voxels().collect(),
+        }
+    }
+}
+
+impl From<OctreeModel> for Model {
+    fn from(octree: OctreeModel) -> Self {
+        Self::from(&octree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        OctreeModel,
+        OctreeNode,
+    };
+    use crate::{
+        scene::BoundingBox,
+        types::{
+            ColorIndex,
+            Model,
+            Vector,
+            Voxel,
+        },
+    };
+
+    #[test]
+    fn it_gets_and_sets_voxels() {
+        let mut octree = OctreeModel::new(Vector::new(5, 3, 2));
+        assert_eq!(octree.side(), 8);
+
+        assert_eq!(octree.set([4, 2, 1], 5), None);
+        assert_eq!(octree.set([4, 2, 1], 6), Some(ColorIndex(5)));
+        assert_eq!(octree.set([0, 0, 0], 7), None);
+        assert_eq!(octree.num_voxels(), 2);
+
+        assert_eq!(octree.get([4, 2, 1]), Some(ColorIndex(6)));
+        assert_eq!(octree.get([1, 0, 0]), None);
+        assert_eq!(octree.get([5, 0, 0]), None);
+
+        assert_eq!(octree.remove([4, 2, 1]), Some(ColorIndex(6)));
+        assert_eq!(octree.remove([0, 0, 0]), Some(ColorIndex(7)));
+        assert!(octree.is_empty());
+        assert_eq!(*octree.root(), OctreeNode::Leaf(ColorIndex(0)));
+    }
+
+    #[test]
+    fn it_merges_uniform_octants() {
+        let mut octree = OctreeModel::new(Vector::new(4, 4, 4));
+        for z in 0..2 {
+            for y in 0..2 {
+                for x in 0..2 {
+                    octree.set([x, y, z], 1);
+                }
+            }
+        }
+        // The root and 8 children, one of which is a filled leaf.
+        assert_eq!(octree.num_nodes(), 9);
+        assert_eq!(octree.num_voxels(), 8);
+
+        octree.set([3, 3, 3], 1);
+        assert_eq!(octree.num_nodes(), 17);
+
+        octree.set([3, 3, 3], 0);
+        assert_eq!(octree.num_nodes(), 9);
+        assert!(octree.memory_usage() > std::mem::size_of::<OctreeModel>());
+    }
+
+    #[test]
+    fn it_queries_regions() {
+        let model = Model {
+            size: Vector::new(16, 16, 16),
+            voxels: vec![
+                Voxel::new([1, 1, 1], 1),
+                Voxel::new([5, 5, 5], 2),
+                Voxel::new([15, 0, 0], 3),
+            ],
+        };
+        let octree = OctreeModel::from(&model);
+
+        let region = BoundingBox {
+            min: Vector::new(0, 0, 0),
+            max: Vector::new(6, 6, 6),
+        };
+        assert_eq!(
+            octree.voxels_in(region).collect::<Vec<_>>(),
+            vec![Voxel::new([1, 1, 1], 1), Voxel::new([5, 5, 5], 2)]
+        );
+
+        assert!(octree.is_region_empty(BoundingBox {
+            min: Vector::new(6, 6, 6),
+            max: Vector::new(100, 100, 100),
+        }));
+
+        let mut voxels = Model::from(&octree).voxels;
+        voxels.sort();
+        let mut expected = model.voxels;
+        expected.sort();
+        assert_eq!(voxels, expected);
+    }
+
+    #[test]
+    fn it_iterates_over_filled_octants() {
+        let mut octree = OctreeModel::new(Vector::new(3, 2, 2));
+        for z in 0..2 {
+            for y in 0..2 {
+                for x in 0..3 {
+                    octree.set([x, y, z], 1);
+                }
+            }
+        }
+
+        assert_eq!(octree.voxels().count(), 12);
+        assert!(octree.voxels().all(|voxel| voxel.point.x < 3));
+    }
+}
@@ -19,7 +19,7 @@ use thiserror::Error;
 
 use crate::{
     chunk::{
-        read_main_chunk,
+        read_main_chunk_with_policy,
         Chunk,
         ChunkId,
     },
@@ -27,12 +27,30 @@ use crate::{
         VoxBuffer,
         VoxData,
     },
+    layout::{
+        Layout,
+        LayoutEntry,
+        RawChunk,
+    },
+    scene::{
+        Node,
+        Scene,
+        SceneError,
+    },
     types::{
+        ColorIndex,
+        Group,
+        Layer,
+        MaterialPalette,
         Palette,
+        Shape,
         Size,
+        Transform,
         Version,
+        VersionPolicy,
         Voxel,
     },
+    validate::ErrorList,
 };
 
 /// Error type returned when reading a VOX file fails.
@@ -58,14 +76,33 @@ pub enum Error {
         xyzi_chunks: Vec<Chunk>,
     },
 
+    /// Multiple `PACK` chunks were found.
+    #[error("Found multiple PACK chunks (at {} and {}).", .chunks[0].offset(), chunks[1].offset())]
+    MultiplePackChunks { chunks: [Chunk; 2] },
+
+    /// The number of models in the `PACK` chunk doesn't match the number of
+    /// `SIZE` and `XYZI` chunk pairs.
+    #[error("PACK chunk specifies {pack_num_models} models, but found {num_models}.")]
+    InvalidPackChunk {
+        pack_num_models: u32,
+        num_models: usize,
+    },
+
     /// Multiple `RGBA` chunks (color palette) were found.
     #[error("Found multiple RGBA chunks (at {} and {}).", .chunks[0].offset(), chunks[1].offset())]
     MultipleRgbaChunks { chunks: [Chunk; 2] },
 
-    /// Unknown material type.
+    /// Unknown numeric material type. This is only returned by the deprecated
+    /// [`crate::types::MaterialType::read`].
+    #[deprecated(note = "`MATL` chunks store the material type as a string.")]
     #[error("Invalid material type: {material_type}")]
     InvalidMaterial { material_type: u8 },
 
+    /// The scene graph is invalid. This is only returned if
+    /// [`ReadOptions::validate_scene`] is set.
+    #[error("Invalid scene graph: {}", ErrorList(.0))]
+    InvalidScene(Vec<SceneError>),
+
     /// An error of the underlying IO
     #[error("IO error")]
     Io(#[from] std::io::Error),
@@ -75,6 +112,26 @@ pub enum Error {
     Utf8(#[from] std::string::FromUtf8Error),
 }
 
+/// Options for [`read_vox_into_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct ReadOptions {
+    /// Validate the scene graph with [`crate::scene::Scene::validate`] before
+    /// passing it to the buffer. If it's invalid, [`Error::InvalidScene`] is
+    /// returned.
+    pub validate_scene: bool,
+
+    /// Which file versions are accepted. By default only
+    /// [`Version::KNOWN`] versions are read, and anything else results in
+    /// [`Error::UnsupportedFileVersion`].
+    pub version_policy: VersionPolicy,
+
+    /// Record the [`Layout`] of the file's chunks and pass it to
+    /// [`VoxBuffer::set_layout`]. This also copies the contents of all chunks
+    /// that aren't parsed into memory, so it's off by default. See
+    /// [`crate::layout`].
+    pub record_layout: bool,
+}
+
 /// Reads a VOX file from the reader into the [`VoxBuffer`]. This function is
 /// useful, if you want to provide your own [`VoxBuffer`].
 ///
@@ -102,31 +159,45 @@ pub enum Error {
 /// read_vox_into(vox_file, &mut counter).unwrap();
 /// println!("{}", counter.num_models);
 /// ```
-pub fn read_vox_into<R: Read + Seek, B: VoxBuffer>(
+pub fn read_vox_into<R: Read + Seek, B: VoxBuffer>(reader: R, buffer: &mut B) -> Result<(), Error> {
+    read_vox_into_with_options(reader, buffer, &ReadOptions::default())
+}
+
+/// Same as [`read_vox_into`], but lets you specify [`ReadOptions`].
+pub fn read_vox_into_with_options<R: Read + Seek, B: VoxBuffer>(
     mut reader: R,
     buffer: &mut B,
+    options: &ReadOptions,
 ) -> Result<(), Error> {
-    let (main_chunk, version) = read_main_chunk(&mut reader)?;
+    let (main_chunk, version) = read_main_chunk_with_policy(&mut reader, options.version_policy)?;
 
     buffer.set_version(version);
 
     //print_chunk(&main_chunk, &mut self.reader, 0)?;
     log::trace!("main chunk: {:#?}", main_chunk);
 
-    //let mut pack_chunk = None;
+    let mut pack_chunk = None;
     let mut size_chunks = vec![];
     let mut xyzi_chunks = vec![];
     let mut rgba_chunk = None;
-    let mut transform_chunks = vec![];
-    let mut group_chunks = vec![];
-    let mut shape_chunks = vec![];
+    let mut node_chunks = vec![];
     let mut layer_chunks = vec![];
+    let mut material_chunks = vec![];
+
+    // Node IDs, layer IDs, material IDs and raw chunk data are only known after
+    // reading them, so these store indices into `layout.entries`, which are
+    // filled in later.
+    let mut node_entries = vec![];
+    let mut layer_entries = vec![];
+    let mut material_entries = vec![];
+    let mut raw_chunks = vec![];
+    let mut layout = Layout::default();
 
     for r in main_chunk.children(&mut reader) {
         let chunk = r?;
 
-        match chunk.id() {
-            /*ChunkId::Pack => {
+        let entry = match chunk.id() {
+            ChunkId::Pack => {
                 log::debug!("read PACK chunk: {:?}", chunk);
                 if pack_chunk.is_some() {
                     return Err(Error::MultiplePackChunks {
@@ -134,9 +205,16 @@ pub fn read_vox_into<R: Read + Seek, B: VoxBuffer>(
                     });
                 }
                 pack_chunk = Some(chunk);
-            }*/
-            ChunkId::Size => size_chunks.push(chunk),
-            ChunkId::Xyzi => xyzi_chunks.push(chunk),
+                LayoutEntry::Pack
+            }
+            ChunkId::Size => {
+                size_chunks.push(chunk);
+                LayoutEntry::Size(size_chunks.len() - 1)
+            }
+            ChunkId::Xyzi => {
+                xyzi_chunks.push(chunk);
+                LayoutEntry::Xyzi(xyzi_chunks.len() - 1)
+            }
             ChunkId::Rgba => {
                 if rgba_chunk.is_some() {
                     return Err(Error::MultipleRgbaChunks {
@@ -144,45 +222,53 @@ pub fn read_vox_into<R: Read + Seek, B: VoxBuffer>(
                     });
                 }
                 rgba_chunk = Some(chunk);
+                LayoutEntry::Rgba
             }
-            /*ChunkId::Note => {
-                let data = chunk.read_content_to_vec(&mut reader)?;
-                log::error!("{:#?}", data);
-                todo!();
-            },*/
-            ChunkId::NTrn => transform_chunks.push(chunk),
-            ChunkId::NGrp => group_chunks.push(chunk),
-            ChunkId::NShp => shape_chunks.push(chunk),
-            ChunkId::Layr => layer_chunks.push(chunk),
-            ChunkId::Unsupported(raw) => {
-                let str_opt = from_utf8(&raw).ok();
-                log::debug!("Skipping unsupported chunk: {:?} ({:?})", raw, str_opt);
+            ChunkId::NTrn | ChunkId::NGrp | ChunkId::NShp => {
+                node_chunks.push(chunk);
+                node_entries.push(layout.entries.len());
+                LayoutEntry::Node(0)
             }
-            id => log::trace!("Skipping unimplemented chunk: {:?}", id),
-        }
-    }
-
-    /*
-    for chunk in &transform_chunks {
-        let transform = Transform::read(chunk.content(&mut reader)?)?;
-        log::debug!("{:#?}", transform);
-    }
+            ChunkId::Layr => {
+                layer_chunks.push(chunk);
+                layer_entries.push(layout.entries.len());
+                LayoutEntry::Layer(0)
+            }
+            ChunkId::Matl => {
+                material_chunks.push(chunk);
+                material_entries.push(layout.entries.len());
+                LayoutEntry::Material(ColorIndex(0))
+            }
+            id => {
+                if let ChunkId::Unsupported(raw) = id {
+                    let str_opt = from_utf8(&raw).ok();
+                    log::debug!("Skipping unsupported chunk: {:?} ({:?})", raw, str_opt);
+                }
+                else {
+                    log::trace!("Skipping unimplemented chunk: {:?}", id);
+                }
 
-    for chunk in &group_chunks {
-        let group = Group::read(chunk.content(&mut reader)?)?;
-        log::debug!("{:#?}", group);
-    }
+                if options.record_layout {
+                    raw_chunks.push((layout.entries.len(), chunk));
+                }
+                LayoutEntry::Raw(RawChunk {
+                    id,
+                    content: vec![],
+                    children: vec![],
+                })
+            }
+        };
 
-    for chunk in &shape_chunks {
-        let shape = Shape::read(chunk.content(&mut reader)?)?;
-        log::debug!("{:#?}", shape);
+        layout.entries.push(entry);
     }
 
-    for chunk in &layer_chunks {
-        let layer = Layer::read(chunk.content(&mut reader)?)?;
-        log::debug!("{:#?}", layer);
+    for (entry_index, chunk) in raw_chunks {
+        layout.entries[entry_index] = LayoutEntry::Raw(RawChunk {
+            id: chunk.id(),
+            content: chunk.read_content_to_vec(&mut reader)?,
+            children: chunk.read_children_to_vec(&mut reader)?,
+        });
     }
-    */
 
     // Call `set_palette` first, so the trait impl has the palette data already when
     // reading the voxels.
@@ -195,11 +281,17 @@ pub fn read_vox_into<R: Read + Seek, B: VoxBuffer>(
         log::trace!("no RGBA chunk found");
     }
 
-    /*let num_models = pack_chunk
-        .map(|pack| Ok::<_, Error>(pack.content(&mut reader)?.read_u32::<LE>()? as usize))
-        .transpose()?
-        .unwrap_or(1);
-    log::trace!("num_models = {}", num_models);*/
+    if !material_chunks.is_empty() {
+        let mut materials = MaterialPalette::default();
+        for (chunk, entry_index) in material_chunks.iter().zip(&material_entries) {
+            let (material_id, material) =
+                MaterialPalette::read_material(chunk.content(&mut reader)?)?;
+            log::trace!("material {} = {:?}", material_id, material);
+            materials.insert(material_id, material);
+            layout.entries[*entry_index] = LayoutEntry::Material(material_id);
+        }
+        buffer.set_materials(materials);
+    }
 
     if xyzi_chunks.len() != size_chunks.len() {
         return Err(Error::InvalidNumberOfSizeAndXyziChunks {
@@ -209,6 +301,20 @@ pub fn read_vox_into<R: Read + Seek, B: VoxBuffer>(
     }
     let num_models = size_chunks.len();
     log::trace!("num_models = {}", num_models);
+
+    // Files written by old versions of MagicaVoxel store the number of models in
+    // the `PACK` chunk. We still infer the number of models from the `SIZE` and
+    // `XYZI` chunks, but check that it matches.
+    if let Some(pack_chunk) = pack_chunk {
+        let pack_num_models = pack_chunk.content(&mut reader)?.read_u32::<LE>()?;
+        log::trace!("pack_num_models = {}", pack_num_models);
+        if pack_num_models as usize != num_models {
+            return Err(Error::InvalidPackChunk {
+                pack_num_models,
+                num_models,
+            });
+        }
+    }
     buffer.set_num_models(num_models);
 
     for (size_chunk, xyzi_chunk) in size_chunks.into_iter().zip(xyzi_chunks) {
@@ -228,13 +334,57 @@ pub fn read_vox_into<R: Read + Seek, B: VoxBuffer>(
         }
     }
 
+    if !node_chunks.is_empty() || !layer_chunks.is_empty() {
+        let mut scene = Scene::default();
+
+        for (chunk, entry_index) in node_chunks.iter().zip(&node_entries) {
+            let node = match chunk.id() {
+                ChunkId::NTrn => Node::Transform(Transform::read(chunk.content(&mut reader)?)?),
+                ChunkId::NGrp => Node::Group(Group::read(chunk.content(&mut reader)?)?),
+                ChunkId::NShp => Node::Shape(Shape::read(chunk.content(&mut reader)?)?),
+                _ => unreachable!(),
+            };
+            log::trace!("node = {:?}", node);
+            layout.entries[*entry_index] = LayoutEntry::Node(node.node_id());
+            scene.nodes.push(node);
+        }
+
+        for (chunk, entry_index) in layer_chunks.iter().zip(&layer_entries) {
+            let layer = Layer::read(chunk.content(&mut reader)?)?;
+            log::trace!("layer = {:?}", layer);
+            layout.entries[*entry_index] = LayoutEntry::Layer(layer.node_id);
+            scene.layers.push(layer);
+        }
+
+        if options.validate_scene {
+            let errors = scene.validate(num_models);
+            if !errors.is_empty() {
+                return Err(Error::InvalidScene(errors));
+            }
+        }
+
+        buffer.set_scene(scene);
+    }
+
+    if options.record_layout {
+        buffer.set_layout(layout);
+    }
+
     Ok(())
 }
 
 /// Reads a VOX file from a reader into [`crate::data::VoxData`].
 pub fn from_reader<R: Read + Seek>(reader: R) -> Result<VoxData, Error> {
+    from_reader_with_options(reader, &ReadOptions::default())
+}
+
+/// Same as [`from_reader`], but lets you specify [`ReadOptions`].
+pub fn from_reader_with_options<R: Read + Seek>(
+    reader: R,
+    options: &ReadOptions,
+) -> Result<VoxData, Error> {
     let mut buffer = VoxData::default();
-    read_vox_into(reader, &mut buffer)?;
+    read_vox_into_with_options(reader, &mut buffer, options)?;
     Ok(buffer)
 }
 
@@ -243,23 +393,63 @@ pub fn from_slice(slice: &[u8]) -> Result<VoxData, Error> {
     from_reader(Cursor::new(slice))
 }
 
+/// Same as [`from_slice`], but lets you specify [`ReadOptions`].
+pub fn from_slice_with_options(slice: &[u8], options: &ReadOptions) -> Result<VoxData, Error> {
+    from_reader_with_options(Cursor::new(slice), options)
+}
+
 /// Reads a VOX file from the specified path into [`crate::data::VoxData`].
 pub fn from_file<P: AsRef<Path>>(path: P) -> Result<VoxData, Error> {
     from_reader(File::open(path)?)
 }
 
+/// Same as [`from_file`], but lets you specify [`ReadOptions`].
+pub fn from_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &ReadOptions,
+) -> Result<VoxData, Error> {
+    from_reader_with_options(File::open(path)?, options)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::{
+        collections::HashMap,
+        io::Cursor,
+    };
 
-    use super::from_slice;
-    use crate::types::{
-        Color,
-        ColorIndex,
-        Model,
-        Point,
-        Vector,
-        Voxel,
+    use byteorder::{
+        WriteBytesExt,
+        LE,
+    };
+
+    use super::{
+        from_slice,
+        from_slice_with_options,
+        read_vox_into_with_options,
+        Error,
+        ReadOptions,
+    };
+    use crate::{
+        chunk::{
+            write_chunk_header,
+            write_content_chunk,
+            ChunkId,
+        },
+        data::VoxData,
+        types::{
+            Color,
+            ColorIndex,
+            MaterialType,
+            Model,
+            Point,
+            Size,
+            Vector,
+            Version,
+            VersionPolicy,
+            Voxel,
+        },
+        writer::write_main_chunk,
     };
 
     fn glider() -> Vec<Voxel> {
@@ -290,7 +480,7 @@ mod tests {
             .collect::<HashMap<Point, ColorIndex>>();
 
         for expected_voxel in expected {
-            let voxel = voxels.get(&Vector::from(expected_voxel.point)).copied();
+            let voxel = voxels.get(&expected_voxel.point).copied();
             assert_eq!(
                 voxel,
                 Some(expected_voxel.color_index),
@@ -368,7 +558,7 @@ mod tests {
 
         let color_index = vox
             .models
-            .get(0)
+            .first()
             .unwrap()
             .voxels
             .first()
@@ -376,4 +566,135 @@ mod tests {
             .color_index;
         assert_eq!(vox.palette[color_index], Color::light_blue());
     }
+
+    #[test]
+    fn it_reads_materials() {
+        let vox = from_slice(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_materials.vox"
+        )))
+        .unwrap();
+
+        assert_eq!(vox.materials.len(), 256);
+
+        let emissive = vox.materials.get(69.into()).unwrap();
+        assert_eq!(emissive.ty(), Some(MaterialType::Emissive));
+        assert_eq!(emissive.flux(), Some(2.0));
+        assert_eq!(emissive.emission(), Some(0.5));
+
+        let metal = vox.materials.get(79.into()).unwrap();
+        assert_eq!(metal.ty(), Some(MaterialType::Metal));
+        assert_eq!(metal.roughness(), Some(0.1));
+
+        // Material 256 is stored for palette index 0.
+        assert!(vox.materials.get(0.into()).is_some());
+    }
+
+    #[test]
+    fn it_checks_pack_chunks() {
+        let mut data = vec![];
+        write_main_chunk(&mut data, Version::default(), |writer| {
+            write_content_chunk(&mut *writer, ChunkId::Pack, |writer| {
+                writer.write_u32::<LE>(3)?;
+                Ok(())
+            })?;
+            write_content_chunk(&mut *writer, ChunkId::Size, |writer| {
+                Size::new(1, 1, 1).write(writer)
+            })?;
+            write_content_chunk(&mut *writer, ChunkId::Xyzi, |writer| {
+                writer.write_u32::<LE>(0)?;
+                Ok(())
+            })
+        })
+        .unwrap();
+
+        assert!(matches!(
+            from_slice(&data),
+            Err(Error::InvalidPackChunk {
+                pack_num_models: 3,
+                num_models: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn it_does_not_trust_the_number_of_shape_models() {
+        let mut data = vec![];
+        write_main_chunk(&mut data, Version::default(), |writer| {
+            write_content_chunk(&mut *writer, ChunkId::NShp, |writer| {
+                writer.write_u32::<LE>(0)?;
+                writer.write_u32::<LE>(0)?;
+                writer.write_u32::<LE>(u32::MAX)?;
+                Ok(())
+            })
+        })
+        .unwrap();
+
+        assert!(from_slice(&data).is_err());
+    }
+
+    #[test]
+    fn it_only_records_the_layout_if_asked_to() {
+        let data = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_multiple_models.vox"
+        ));
+        assert!(from_slice(data).unwrap().layout.is_empty());
+
+        let options = ReadOptions {
+            record_layout: true,
+            ..Default::default()
+        };
+        let vox = from_slice_with_options(data, &options).unwrap();
+        assert_eq!(vox.layout.raw_chunks().count(), 26);
+    }
+
+    #[test]
+    fn it_does_not_trust_the_length_of_raw_chunks() {
+        // A chunk that claims to have 1 GiB of children, but the file ends
+        // after its header.
+        let mut data = vec![];
+        write_main_chunk(&mut data, Version::default(), |writer| {
+            write_chunk_header(&mut *writer, ChunkId::Note, 0, 1 << 30)?;
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(from_slice(&data).unwrap().layout.is_empty());
+
+        let options = ReadOptions {
+            record_layout: true,
+            ..Default::default()
+        };
+        assert!(from_slice_with_options(&data, &options).is_err());
+    }
+
+    #[test]
+    fn it_applies_the_version_policy() {
+        let mut data = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_single_model_default_palette.vox"
+        ))
+        .to_vec();
+
+        data[4..8].copy_from_slice(&200u32.to_le_bytes());
+        assert_eq!(from_slice(&data).unwrap().version, Version::V200);
+
+        data[4..8].copy_from_slice(&300u32.to_le_bytes());
+        assert!(matches!(
+            from_slice(&data),
+            Err(Error::UnsupportedFileVersion {
+                version: Version(300)
+            })
+        ));
+
+        let options = ReadOptions {
+            version_policy: VersionPolicy::Permissive,
+            ..Default::default()
+        };
+        let mut vox = VoxData::default();
+        read_vox_into_with_options(Cursor::new(&data), &mut vox, &options).unwrap();
+        assert_eq!(vox.version, Version(300));
+        assert_eq!(vox.models.len(), 1);
+    }
 }
@@ -0,0 +1,322 @@
+//! Resampling of models by integer factors.
+//!
+//! [`upscale`] replicates each voxel into a cube of voxels. Since models can't
+//! be larger than [`MAX_MODEL_SIZE`], the result is split into multiple parts
+//! if necessary. [`downsample`] merges cubes of voxels into single voxels, and
+//! picks their colors by majority vote.
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    edit::{
+        model_to_point,
+        point_to_model,
+        EditModel,
+        Part,
+    },
+    scene::BoundingBox,
+    types::{
+        ColorIndex,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// How empty cells are handled by [`downsample`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum EmptySpace {
+    /// Empty cells are ignored. An output voxel is filled if any of its cells
+    /// is filled.
+    Ignore,
+
+    /// Empty space takes part in the vote like a color. An output voxel is
+    /// empty if more of its cells are empty than have any single color.
+    Vote,
+
+    /// An output voxel is filled if at least this many of its cells are
+    /// filled.
+    MinFilled(usize),
+}
+
+/// The largest factor accepted by [`upscale`]. With larger factors, points of
+/// the scaled model would overflow `i32`.
+pub const MAX_UPSCALE_FACTOR: u32 = i32::MAX as u32 / MAX_MODEL_SIZE;
+
+/// Scales a model up by `factor`, by replicating each voxel `factor` times
+/// along each axis.
+///
+/// If the scaled model is larger than [`MAX_MODEL_SIZE`] along an axis, it's
+/// split into multiple parts. The parts are ordered by z, then y, then x
+/// offset, and include empty parts.
+///
+/// # Panics
+///
+/// Panics if `factor` is 0 or larger than [`MAX_UPSCALE_FACTOR`].
+pub fn upscale<M: EditModel>(model: &M, factor: u32) -> Vec<Part<M>> {
+    assert!(factor > 0, "Scaling factor must not be 0");
+    assert!(
+        factor <= MAX_UPSCALE_FACTOR,
+        "Scaling factor {} is larger than {}",
+        factor,
+        MAX_UPSCALE_FACTOR
+    );
+
+    let size = model.size().map(|x| x.min(MAX_MODEL_SIZE) * factor);
+    let num_parts = size.map(|x| x.max(1).div_ceil(MAX_MODEL_SIZE));
+
+    let mut parts = vec![];
+    for z in 0..num_parts.z {
+        for y in 0..num_parts.y {
+            for x in 0..num_parts.x {
+                let offset = Vector::new(x, y, z).map(|x| x * MAX_MODEL_SIZE);
+                let part_size = Vector::new(
+                    (size.x - offset.x).min(MAX_MODEL_SIZE),
+                    (size.y - offset.y).min(MAX_MODEL_SIZE),
+                    (size.z - offset.z).min(MAX_MODEL_SIZE),
+                );
+                parts.push(Part {
+                    model: M::new(part_size),
+                    offset: offset.map(|x| x as i32),
+                });
+            }
+        }
+    }
+
+    let part_index = |point: Vector<i32>| {
+        let part = point.map(|x| x as u32 / MAX_MODEL_SIZE);
+        (part.x + num_parts.x * (part.y + num_parts.y * part.z)) as usize
+    };
+
+    let bounds = BoundingBox::of_size(model.size());
+    let factor = factor as i32;
+    let mut part_voxels = vec![vec![]; parts.len()];
+    for voxel in model.voxels() {
+        if !bounds.contains(point_to_model(voxel.point)) {
+            continue;
+        }
+        let origin = point_to_model(voxel.point).map(|x| x * factor);
+
+        for z in 0..factor {
+            for y in 0..factor {
+                for x in 0..factor {
+                    let point = origin + Vector::new(x, y, z);
+                    let i = part_index(point);
+                    let point = model_to_point(point - parts[i].offset);
+                    part_voxels[i].push(Voxel::new(point, voxel.color_index));
+                }
+            }
+        }
+    }
+
+    for (part, voxels) in parts.iter_mut().zip(part_voxels) {
+        part.model.set_all(voxels);
+    }
+
+    parts
+}
+
+/// Scales a model down by `factor`. Each output voxel covers a cube of
+/// `factor` cells along each axis, and gets the color that most of these cells
+/// have. `empty` decides how empty cells are handled.
+///
+/// Ties are broken in favor of filled cells, and then the lower color index.
+/// Factors larger than [`MAX_MODEL_SIZE`] have the same effect as
+/// `MAX_MODEL_SIZE`.
+///
+/// # Panics
+///
+/// Panics if `factor` is 0.
+pub fn downsample<M: EditModel>(model: &M, factor: u32, empty: EmptySpace) -> M {
+    assert!(factor > 0, "Scaling factor must not be 0");
+
+    let size = model.size().map(|x| x.min(MAX_MODEL_SIZE));
+    // All cells are covered by a single output voxel at this factor already.
+    let factor = factor.min(MAX_MODEL_SIZE);
+
+    // Count the colors for each output voxel. The votes are ordered, so the
+    // voxels are always set in the same order.
+    let bounds = BoundingBox::of_size(model.size());
+    let mut votes: BTreeMap<Vector<i32>, Vec<(ColorIndex, usize)>> = BTreeMap::new();
+    for voxel in model.voxels() {
+        if !bounds.contains(point_to_model(voxel.point)) {
+            continue;
+        }
+        let point = point_to_model(voxel.point).map(|x| x / factor as i32);
+        let counts = votes.entry(point).or_default();
+        match counts.iter_mut().find(|(c, _)| *c == voxel.color_index) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((voxel.color_index, 1)),
+        }
+    }
+
+    let mut downsampled = M::new(size.map(|x| x.div_ceil(factor)));
+
+    downsampled.set_all(votes.into_iter().filter_map(|(point, counts)| {
+        let (color_index, count) = counts
+            .iter()
+            .copied()
+            .max_by_key(|(color_index, count)| (*count, std::cmp::Reverse(*color_index)))
+            .expect("at least one vote");
+        let filled = counts.iter().map(|(_, count)| count).sum::<usize>();
+
+        let is_filled = match empty {
+            EmptySpace::Ignore => true,
+            EmptySpace::Vote => {
+                // Number of cells that lie within the model.
+                let factor = factor as i32;
+                let cells = |p: i32, n: u32| (n as i32 - p * factor).clamp(0, factor) as usize;
+                let cells =
+                    cells(point.x, size.x) * cells(point.y, size.y) * cells(point.z, size.z);
+                count >= cells.saturating_sub(filled)
+            }
+            EmptySpace::MinFilled(min_filled) => filled >= min_filled,
+        };
+
+        is_filled.then(|| Voxel::new(model_to_point(point), color_index))
+    }));
+
+    downsampled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        downsample,
+        upscale,
+        EmptySpace,
+    };
+    use crate::{
+        dense::DenseModel,
+        edit::EditModel,
+        scene::BoundingBox,
+        sparse::SparseModel,
+        types::{
+            ColorIndex,
+            Model,
+            Vector,
+            Voxel,
+        },
+    };
+
+    #[test]
+    fn it_upscales_models() {
+        let mut model = DenseModel::new(Vector::new(2, 1, 1));
+        model.set([1, 0, 0], 3);
+
+        let parts = upscale(&model, 3);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].offset, Vector::new(0, 0, 0));
+
+        let scaled = &parts[0].model;
+        assert_eq!(scaled.size(), Vector::new(6, 3, 3));
+        assert_eq!(scaled.num_voxels(), 27);
+        assert_eq!(scaled.get([2, 0, 0]), None);
+        assert_eq!(scaled.get([3, 2, 2]), Some(ColorIndex(3)));
+    }
+
+    #[test]
+    fn it_splits_large_models() {
+        // Points are stored as unsigned bytes.
+        let mut model = SparseModel::new(Vector::new(200, 1, 1));
+        model.insert([199u8 as i8, 0, 0], 1);
+
+        let parts = upscale(&model, 2);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].model.size(), Vector::new(256, 2, 2));
+        assert_eq!(parts[1].model.size(), Vector::new(144, 2, 2));
+        assert_eq!(parts[1].offset, Vector::new(256, 0, 0));
+
+        // x = 199 is scaled to 398 and 399, which is 143 in the second part.
+        assert!(parts[0].model.is_empty());
+        assert_eq!(parts[1].model.num_voxels(), 8);
+        assert_eq!(parts[1].model.get([143u8 as i8, 1, 1]), Some(ColorIndex(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Scaling factor")]
+    fn it_refuses_too_large_upscale_factors() {
+        let model = SparseModel::new(Vector::new(1, 1, 1));
+        upscale(&model, u32::MAX);
+    }
+
+    #[test]
+    fn it_downsamples_by_large_factors() {
+        let mut model = DenseModel::new(Vector::new(3, 1, 1));
+        model.set([2, 0, 0], 1);
+
+        for factor in [3, 256, u32::MAX] {
+            let downsampled = downsample(&model, factor, EmptySpace::Vote);
+            assert_eq!(downsampled.size(), Vector::new(1, 1, 1));
+            assert_eq!(downsampled.num_voxels(), 0);
+
+            let downsampled = downsample(&model, factor, EmptySpace::Ignore);
+            assert_eq!(downsampled.get([0, 0, 0]), Some(ColorIndex(1)));
+        }
+    }
+
+    #[test]
+    fn it_downsamples_by_majority_vote() {
+        let mut model = DenseModel::new(Vector::new(5, 2, 2));
+        model.fill(
+            BoundingBox {
+                min: Vector::new(0, 0, 0),
+                max: Vector::new(2, 2, 2),
+            },
+            ColorIndex(1),
+        );
+        model.set([0, 0, 0], 2);
+        model.set([2, 0, 0], 3);
+        model.set([3, 0, 0], 3);
+        model.set([4, 0, 0], 4);
+
+        let downsampled = downsample(&model, 2, EmptySpace::Ignore);
+        assert_eq!(downsampled.size(), Vector::new(3, 1, 1));
+        assert_eq!(downsampled.get([0, 0, 0]), Some(ColorIndex(1)));
+        assert_eq!(downsampled.get([1, 0, 0]), Some(ColorIndex(3)));
+        assert_eq!(downsampled.get([2, 0, 0]), Some(ColorIndex(4)));
+
+        // 2 of 8 cells are filled.
+        let downsampled = downsample(&model, 2, EmptySpace::Vote);
+        assert_eq!(downsampled.get([0, 0, 0]), Some(ColorIndex(1)));
+        assert_eq!(downsampled.get([1, 0, 0]), None);
+        // 1 of 4 cells is filled at the edge.
+        assert_eq!(downsampled.get([2, 0, 0]), None);
+
+        let downsampled = downsample(&model, 2, EmptySpace::MinFilled(2));
+        assert_eq!(downsampled.num_voxels(), 2);
+    }
+
+    #[test]
+    fn it_downsamples_voxel_lists_in_order() {
+        // The last voxel is outside of the model, and is skipped.
+        let model = Model {
+            size: Vector::new(4, 4, 1),
+            voxels: vec![
+                Voxel::new([3, 3, 0], 4),
+                Voxel::new([0, 3, 0], 3),
+                Voxel::new([3, 0, 0], 2),
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([9, 0, 0], 5),
+            ],
+        };
+
+        let downsampled = downsample(&model, 2, EmptySpace::Ignore);
+        assert_eq!(
+            downsampled.voxels,
+            vec![
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([0, 1, 0], 3),
+                Voxel::new([1, 0, 0], 2),
+                Voxel::new([1, 1, 0], 4),
+            ]
+        );
+    }
+}
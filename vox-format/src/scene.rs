@@ -0,0 +1,1601 @@
+//! The scene graph of a VOX file and utilities to flatten it into world-space
+//! voxels.
+//!
+//! A scene is made up of [`Transform`], [`Group`] and [`Shape`] nodes. The root
+//! of the scene is always the transform with node ID `0`. Transforms have
+//! exactly one child, groups can have any number of children (which are always
+//! transforms), and shapes reference the models that are placed in the scene.
+
+use std::{
+    collections::{
+        hash_map::Entry,
+        HashMap,
+        HashSet,
+    },
+    convert::TryFrom,
+    iter::FromIterator,
+    slice,
+};
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use thiserror::Error;
+
+use crate::{
+    data::VoxData,
+    types::{
+        Attributes,
+        ColorIndex,
+        Group,
+        Layer,
+        Model,
+        Point,
+        Rotation,
+        Shape,
+        ShapeModel,
+        Size,
+        Transform,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// The node ID of the root transform.
+pub const ROOT_NODE_ID: u32 = 0;
+
+/// A node in the scene graph.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Node {
+    Transform(Transform),
+    Group(Group),
+    Shape(Shape),
+}
+
+impl Node {
+    /// Returns the node's ID.
+    pub fn node_id(&self) -> u32 {
+        match self {
+            Node::Transform(transform) => transform.node_id,
+            Node::Group(group) => group.node_id,
+            Node::Shape(shape) => shape.node_id,
+        }
+    }
+
+    /// Returns the node's attributes.
+    pub fn attributes(&self) -> &Attributes {
+        match self {
+            Node::Transform(transform) => &transform.attributes,
+            Node::Group(group) => &group.attributes,
+            Node::Shape(shape) => &shape.attributes,
+        }
+    }
+
+    /// Returns the IDs of the node's children.
+    pub fn children(&self) -> Vec<u32> {
+        match self {
+            Node::Transform(transform) => vec![transform.child_node_id],
+            Node::Group(group) => group.children.clone(),
+            Node::Shape(_) => vec![],
+        }
+    }
+}
+
+/// An error in a scene graph. These are returned by [`Scene::validate`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Error)]
+pub enum SceneError {
+    /// Multiple nodes have the same ID.
+    #[error("Duplicate node ID: {node_id}")]
+    DuplicateNodeId { node_id: u32 },
+
+    /// Multiple layers have the same ID.
+    #[error("Duplicate layer ID: {layer_id}")]
+    DuplicateLayerId { layer_id: u32 },
+
+    /// The scene has nodes, but no root node.
+    #[error("Missing root node")]
+    MissingRoot,
+
+    /// The root node is not a transform.
+    #[error("Root node {node_id} is not a transform")]
+    InvalidRoot { node_id: u32 },
+
+    /// A node references a child that doesn't exist.
+    #[error("Node {node_id} references missing child {child_node_id}")]
+    DanglingChild { node_id: u32, child_node_id: u32 },
+
+    /// A node has a child of the wrong type. Transforms must have a group or
+    /// shape as child, and groups must only have transforms as children.
+    #[error("Node {node_id} has invalid child {child_node_id}")]
+    InvalidChild { node_id: u32, child_node_id: u32 },
+
+    /// A node is the child of multiple nodes.
+    #[error("Node {node_id} has multiple parents")]
+    MultipleParents { node_id: u32 },
+
+    /// The node is part of a cycle.
+    #[error("Node {node_id} is part of a cycle")]
+    Cycle { node_id: u32 },
+
+    /// A shape references a model that doesn't exist.
+    #[error("Shape {node_id} references missing model {model_id}")]
+    MissingModel { node_id: u32, model_id: u32 },
+
+    /// A transform references a layer that doesn't exist.
+    #[error("Transform {node_id} references missing layer {layer_id}")]
+    MissingLayer { node_id: u32, layer_id: u32 },
+}
+
+/// The scene graph of a VOX file.
+///
+/// The nodes are stored in the order they appear in the file. Files that were
+/// written without a scene graph have an empty scene. See [`Scene::instances`]
+/// for how these are handled.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Scene {
+    pub nodes: Vec<Node>,
+    pub layers: Vec<Layer>,
+}
+
+impl Scene {
+    /// Creates a scene with a root group, that contains an instance of each of
+    /// the `num_models` models with an identity transform. This is how files
+    /// without a scene graph are displayed.
+    pub fn from_models(num_models: usize) -> Self {
+        let mut builder = SceneBuilder::new();
+        for model_id in 0..num_models {
+            builder.add_instance(
+                ROOT_NODE_ID,
+                u32::try_from(model_id).expect("int overflow"),
+                SceneTransform::identity(),
+            );
+        }
+        builder.build()
+    }
+
+    /// Tests if the scene contains neither nodes nor layers.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.layers.is_empty()
+    }
+
+    /// Looks up the node with ID `node_id`. This performs a linear search.
+    pub fn get_node(&self, node_id: u32) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.node_id() == node_id)
+    }
+
+    /// Looks up the layer with ID `layer_id`. This performs a linear search.
+    pub fn get_layer(&self, layer_id: u32) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.node_id == layer_id)
+    }
+
+    /// Returns all nodes whose name (`_name` attribute) is `name`.
+    pub fn find_by_name(&self, name: &str) -> Vec<&Node> {
+        self.nodes
+            .iter()
+            .filter(|node| node.attributes().name() == Some(name))
+            .collect()
+    }
+
+    /// Returns all transforms that are on the layer `layer_id`. In MagicaVoxel
+    /// only transforms are assigned to layers, and their children belong to
+    /// the same layer.
+    pub fn layer_members(&self, layer_id: u32) -> Vec<&Transform> {
+        self.nodes
+            .iter()
+            .filter_map(|node| {
+                match node {
+                    Node::Transform(transform) if transform.layer_id == Some(layer_id) => {
+                        Some(transform)
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the world transform of a node at frame `0`, by composing the
+    /// transforms of the node and all its ancestors. Returns `None` if there is
+    /// no node with this ID, or if it's not connected to the root.
+    pub fn world_transform(&self, node_id: u32) -> Option<SceneTransform> {
+        self.world_transform_at(node_id, 0)
+    }
+
+    /// Same as [`Scene::world_transform`], but evaluates transforms at `frame`.
+    pub fn world_transform_at(&self, node_id: u32, frame: u32) -> Option<SceneTransform> {
+        let mut nodes = HashMap::new();
+        let mut parents = HashMap::new();
+        for node in &self.nodes {
+            nodes.entry(node.node_id()).or_insert(node);
+            for child_node_id in node.children() {
+                parents
+                    .entry(child_node_id)
+                    .or_insert_with(|| node.node_id());
+            }
+        }
+
+        let mut transform = SceneTransform::identity();
+        let mut visited = HashSet::new();
+        let mut current = node_id;
+
+        loop {
+            if !visited.insert(current) {
+                return None;
+            }
+
+            if let Node::Transform(node) = nodes.get(&current)? {
+                transform = node.transform_at(frame).then(&transform);
+            }
+
+            if current == ROOT_NODE_ID {
+                return Some(transform);
+            }
+            current = *parents.get(&current)?;
+        }
+    }
+
+    /// Brings the scene into a canonical form, so that equal scenes are always
+    /// written the same way:
+    ///
+    ///  - Nodes are renumbered in depth-first order starting at the root, and
+    ///    stored in that order. Nodes that aren't reachable from the root
+    ///    follow in their original order.
+    ///  - Layers are sorted by ID.
+    ///  - All attributes are sorted by key.
+    pub fn canonicalize(&mut self) {
+        let mut index = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            index.entry(node.node_id()).or_insert(i);
+        }
+
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        let starts = index
+            .get(&ROOT_NODE_ID)
+            .copied()
+            .into_iter()
+            .chain(0..self.nodes.len());
+        for start in starts {
+            let mut stack = vec![start];
+            while let Some(i) = stack.pop() {
+                if !visited.insert(i) {
+                    continue;
+                }
+                order.push(i);
+
+                // Push in reverse, so that the first child is visited first.
+                for child_node_id in self.nodes[i].children().iter().rev() {
+                    if let Some(&j) = index.get(child_node_id) {
+                        stack.push(j);
+                    }
+                }
+            }
+        }
+
+        let mut new_ids = HashMap::new();
+        for (new_id, &i) in order.iter().enumerate() {
+            let old_id = self.nodes[i].node_id();
+            if index[&old_id] == i {
+                new_ids.insert(old_id, u32::try_from(new_id).expect("int overflow"));
+            }
+        }
+        let remap = |node_id: &mut u32| {
+            if let Some(new_id) = new_ids.get(node_id) {
+                *node_id = *new_id;
+            }
+        };
+
+        let mut nodes = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        for (new_id, i) in order.into_iter().enumerate() {
+            let mut node = nodes[i].take().expect("node visited twice");
+            let new_id = u32::try_from(new_id).expect("int overflow");
+
+            match &mut node {
+                Node::Transform(transform) => {
+                    transform.node_id = new_id;
+                    remap(&mut transform.child_node_id);
+                    transform.attributes.sort();
+                    transform.frames.iter_mut().for_each(Attributes::sort);
+                }
+                Node::Group(group) => {
+                    group.node_id = new_id;
+                    group.children.iter_mut().for_each(remap);
+                    group.attributes.sort();
+                }
+                Node::Shape(shape) => {
+                    shape.node_id = new_id;
+                    shape.attributes.sort();
+                    for model in &mut shape.models {
+                        model.attributes.sort();
+                    }
+                }
+            }
+
+            self.nodes.push(node);
+        }
+
+        self.layers.sort_by_key(|layer| layer.node_id);
+        for layer in &mut self.layers {
+            layer.attributes.sort();
+        }
+    }
+
+    /// Validates the scene graph and returns all errors that were found. An
+    /// empty scene is always valid.
+    ///
+    /// `num_models` is the number of models in the file. It is used to check
+    /// that shapes only reference existing models.
+    ///
+    /// ```
+    /// # let vox_data = vox_format::from_file("../test_files/test_multiple_models.vox").unwrap();
+    /// let errors = vox_data.scene.validate(vox_data.models.len());
+    /// assert!(errors.is_empty());
+    /// ```
+    pub fn validate(&self, num_models: usize) -> Vec<SceneError> {
+        let mut errors = vec![];
+
+        if self.nodes.is_empty() {
+            return errors;
+        }
+
+        // Like `Scene::instances`, we use the first node, if there are duplicates.
+        let mut nodes = HashMap::new();
+        for node in &self.nodes {
+            match nodes.entry(node.node_id()) {
+                Entry::Occupied(_) => {
+                    errors.push(SceneError::DuplicateNodeId {
+                        node_id: node.node_id(),
+                    })
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(node);
+                }
+            }
+        }
+
+        let mut layer_ids = HashSet::new();
+        for layer in &self.layers {
+            if !layer_ids.insert(layer.node_id) {
+                errors.push(SceneError::DuplicateLayerId {
+                    layer_id: layer.node_id,
+                });
+            }
+        }
+
+        match nodes.get(&ROOT_NODE_ID) {
+            Some(Node::Transform(_)) => {}
+            Some(_) => {
+                errors.push(SceneError::InvalidRoot {
+                    node_id: ROOT_NODE_ID,
+                })
+            }
+            None => errors.push(SceneError::MissingRoot),
+        }
+
+        let mut num_parents = HashMap::new();
+
+        for node in &self.nodes {
+            let node_id = node.node_id();
+
+            match node {
+                Node::Transform(transform) => {
+                    if let Some(layer_id) = transform.layer_id {
+                        if !layer_ids.contains(&layer_id) {
+                            errors.push(SceneError::MissingLayer { node_id, layer_id });
+                        }
+                    }
+                }
+                Node::Shape(shape) => {
+                    for model in &shape.models {
+                        if model.model_id as usize >= num_models {
+                            errors.push(SceneError::MissingModel {
+                                node_id,
+                                model_id: model.model_id,
+                            });
+                        }
+                    }
+                }
+                Node::Group(_) => {}
+            }
+
+            for child_node_id in node.children() {
+                *num_parents.entry(child_node_id).or_insert(0usize) += 1;
+
+                let child = if let Some(child) = nodes.get(&child_node_id) {
+                    child
+                }
+                else {
+                    errors.push(SceneError::DanglingChild {
+                        node_id,
+                        child_node_id,
+                    });
+                    continue;
+                };
+
+                let valid_child = match node {
+                    Node::Transform(_) => !matches!(child, Node::Transform(_)),
+                    Node::Group(_) => matches!(child, Node::Transform(_)),
+                    Node::Shape(_) => false,
+                };
+
+                if !valid_child {
+                    errors.push(SceneError::InvalidChild {
+                        node_id,
+                        child_node_id,
+                    });
+                }
+            }
+        }
+
+        let mut multiple_parents = num_parents
+            .into_iter()
+            .filter(|(_, n)| *n > 1)
+            .map(|(node_id, _)| node_id)
+            .collect::<Vec<_>>();
+        multiple_parents.sort_unstable();
+        errors.extend(
+            multiple_parents
+                .into_iter()
+                .map(|node_id| SceneError::MultipleParents { node_id }),
+        );
+
+        // Find cycles with a depth-first search over all nodes. `finished` contains
+        // nodes whose children have all been visited.
+        let mut finished = HashSet::new();
+        for node in &self.nodes {
+            find_cycles(&nodes, node.node_id(), &mut finished, &mut errors);
+        }
+
+        errors
+    }
+
+    /// Walks the scene graph from the root transform and returns every visible
+    /// model instance with its world transform.
+    ///
+    /// Nodes that are hidden, or transforms that are on a hidden layer, are
+    /// skipped together with their children. Dangling node IDs and cycles are
+    /// skipped as well.
+    ///
+    /// If the scene is empty, `num_models` instances are returned, one for each
+    /// model, all with the identity transform.
+    ///
+    /// This evaluates the scene at frame `0`. Use [`Scene::instances_at`] for
+    /// animated scenes.
+    pub fn instances(&self, num_models: usize) -> Vec<Instance> {
+        self.instances_at(num_models, 0)
+    }
+
+    /// Same as [`Scene::instances`], but evaluates transforms and shapes at
+    /// `frame`. See [`crate::animation`] for how keyframes are evaluated.
+    pub fn instances_at(&self, num_models: usize, frame: u32) -> Vec<Instance> {
+        if self.nodes.is_empty() {
+            return (0..num_models)
+                .map(|model_id| {
+                    Instance {
+                        model_id: u32::try_from(model_id).expect("int overflow"),
+                        shape_node_id: None,
+                        transform_node_id: None,
+                        layer_id: None,
+                        transform: SceneTransform::identity(),
+                    }
+                })
+                .collect();
+        }
+
+        let mut visitor = InstanceVisitor {
+            frame,
+            nodes: HashMap::new(),
+            layers: HashMap::from_iter(self.layers.iter().map(|layer| (layer.node_id, layer))),
+            instances: vec![],
+        };
+        for node in &self.nodes {
+            visitor.nodes.entry(node.node_id()).or_insert(node);
+        }
+
+        visitor.visit(ROOT_NODE_ID, SceneTransform::identity());
+
+        visitor.instances
+    }
+}
+
+/// Walks the graph from `node_id` depth-first and reports every node that is
+/// reached again from itself. The graph is read from a file, so an explicit
+/// stack is used instead of recursion.
+fn find_cycles(
+    nodes: &HashMap<u32, &Node>,
+    node_id: u32,
+    finished: &mut HashSet<u32>,
+    errors: &mut Vec<SceneError>,
+) {
+    // The nodes on the current path, each with its children that are left to
+    // visit, in reverse order.
+    let mut path = HashSet::new();
+    let mut stack: Vec<(u32, Vec<u32>)> = vec![];
+
+    let mut next = Some(node_id);
+    loop {
+        if let Some(node_id) = next.take() {
+            if finished.contains(&node_id) {
+                // Already checked.
+            }
+            else if path.contains(&node_id) {
+                errors.push(SceneError::Cycle { node_id });
+            }
+            else if let Some(node) = nodes.get(&node_id) {
+                path.insert(node_id);
+                let mut children = node.children();
+                children.reverse();
+                stack.push((node_id, children));
+            }
+            else {
+                finished.insert(node_id);
+            }
+        }
+
+        match stack.last_mut() {
+            Some((_, children)) if !children.is_empty() => next = children.pop(),
+            Some((node_id, _)) => {
+                path.remove(node_id);
+                finished.insert(*node_id);
+                stack.pop();
+            }
+            None => break,
+        }
+    }
+}
+
+/// A step of [`InstanceVisitor::visit`].
+enum Visit<'a> {
+    /// Visit a node. `parent` is the transform node that is the direct parent
+    /// of the node.
+    Enter {
+        node_id: u32,
+        transform: SceneTransform,
+        parent: Option<&'a Transform>,
+    },
+
+    /// All children of the node were visited.
+    Exit { node_id: u32 },
+}
+
+struct InstanceVisitor<'a> {
+    frame: u32,
+    nodes: HashMap<u32, &'a Node>,
+    layers: HashMap<u32, &'a Layer>,
+    instances: Vec<Instance>,
+}
+
+impl<'a> InstanceVisitor<'a> {
+    /// Walks the graph from `node_id` depth-first. The graph is read from a
+    /// file, so an explicit stack is used instead of recursion.
+    fn visit(&mut self, node_id: u32, transform: SceneTransform) {
+        let mut path = HashSet::new();
+        let mut stack = vec![Visit::Enter {
+            node_id,
+            transform,
+            parent: None,
+        }];
+
+        while let Some(visit) = stack.pop() {
+            let (node_id, transform, parent) = match visit {
+                Visit::Enter {
+                    node_id,
+                    transform,
+                    parent,
+                } => (node_id, transform, parent),
+                Visit::Exit { node_id } => {
+                    path.remove(&node_id);
+                    continue;
+                }
+            };
+
+            if path.contains(&node_id) {
+                log::warn!("Cycle in scene graph at node {}", node_id);
+                continue;
+            }
+
+            let node = if let Some(node) = self.nodes.get(&node_id) {
+                *node
+            }
+            else {
+                log::warn!("Scene graph references missing node {}", node_id);
+                continue;
+            };
+
+            if node.attributes().is_hidden() {
+                continue;
+            }
+
+            path.insert(node_id);
+            stack.push(Visit::Exit { node_id });
+
+            match node {
+                Node::Transform(node) => {
+                    let layer_hidden = node
+                        .layer_id
+                        .and_then(|layer_id| self.layers.get(&layer_id))
+                        .map(|layer| layer.attributes.is_hidden())
+                        .unwrap_or_default();
+
+                    if !layer_hidden {
+                        let local = node.transform_at(self.frame);
+                        stack.push(Visit::Enter {
+                            node_id: node.child_node_id,
+                            transform: transform.then(&local),
+                            parent: Some(node),
+                        });
+                    }
+                }
+                Node::Group(node) => {
+                    // Pushed in reverse, so that children are visited in order.
+                    for child_node_id in node.children.iter().rev() {
+                        stack.push(Visit::Enter {
+                            node_id: *child_node_id,
+                            transform,
+                            parent: None,
+                        });
+                    }
+                }
+                Node::Shape(node) => {
+                    if let Some(model) = node.model_at(self.frame) {
+                        self.instances.push(Instance {
+                            model_id: model.model_id,
+                            shape_node_id: Some(node.node_id),
+                            transform_node_id: parent.map(|parent| parent.node_id),
+                            layer_id: parent.and_then(|parent| parent.layer_id),
+                            transform,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A translation combined with a [`Rotation`]. Points are first rotated and
+/// then translated.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SceneTransform {
+    pub translation: Vector<i32>,
+    pub rotation: Rotation,
+}
+
+impl SceneTransform {
+    /// Creates a transform from a translation and a rotation.
+    pub fn new(translation: Vector<i32>, rotation: Rotation) -> Self {
+        Self {
+            translation,
+            rotation,
+        }
+    }
+
+    /// The identity transform.
+    pub fn identity() -> Self {
+        Self::default()
+    }
+
+    /// Returns the transform that first applies `child` and then `self`.
+    pub fn then(&self, child: &SceneTransform) -> Self {
+        Self {
+            translation: self.translation + self.rotation.rotate(child.translation),
+            rotation: self.rotation.compose(child.rotation),
+        }
+    }
+
+    /// Returns the frame attributes (`_t` and `_r`) for this transform. Zero
+    /// translation and identity rotation are omitted.
+    pub fn to_frame_attributes(&self) -> Attributes {
+        let mut attributes = Attributes::default();
+
+        if self.rotation != Rotation::identity() {
+            attributes.insert("_r", self.rotation.to_byte().to_string());
+        }
+
+        if self.translation != Vector::default() {
+            attributes.insert(
+                "_t",
+                format!(
+                    "{} {} {}",
+                    self.translation.x, self.translation.y, self.translation.z
+                ),
+            );
+        }
+
+        attributes
+    }
+
+    /// Transforms a point.
+    pub fn transform_point(&self, point: Vector<i32>) -> Vector<i32> {
+        self.rotation.rotate(point) + self.translation
+    }
+
+    /// Transforms the coordinates of a voxel in a model of size `size` to world
+    /// coordinates.
+    ///
+    /// MagicaVoxel rotates models around their center. This means the voxel at
+    /// `point` is first moved by `-size / 2`, then rotated and translated. The
+    /// computation is done on voxel centers, so that rotated models with even
+    /// sizes occupy the same cells as MagicaVoxel shows them.
+    ///
+    /// VOX files store voxel coordinates as unsigned bytes, so the components
+    /// of `point` are interpreted as `u8`.
+    pub fn model_to_world(&self, point: Point, size: Size) -> Vector<i32> {
+        // Voxel center relative to the model center, scaled by 2 to stay in
+        // integers.
+        let point = point.map(|x| i32::from(x as u8));
+        let size = size.map(|x| i32::try_from(x).expect("int overflow"));
+        let center = point.map(|x| 2 * x + 1) - size;
+
+        (self.rotation.rotate(center) + self.translation.map(|x| 2 * x)).map(|x| x.div_euclid(2))
+    }
+}
+
+/// A builder for [`Scene`]s.
+///
+/// The builder allocates node IDs and takes care of the node structure
+/// MagicaVoxel expects: Every group and every shape is wrapped in a
+/// [`Transform`] node, which holds the translation, rotation, name and layer.
+/// Therefore all methods identify objects in the scene by the node ID of their
+/// transform.
+///
+/// The root of the scene is [`ROOT_NODE_ID`], which wraps the root group.
+///
+/// ```
+/// # use vox_format::{scene::{SceneBuilder, SceneTransform, ROOT_NODE_ID}, types::{Rotation, Vector}};
+/// let mut builder = SceneBuilder::new();
+///
+/// let layer = builder.add_layer("props");
+/// let group = builder.add_group(ROOT_NODE_ID, SceneTransform::identity());
+/// builder.set_name(group, "barrels");
+///
+/// for i in 0..4 {
+///     let barrel = builder.add_instance(
+///         group,
+///         0,
+///         SceneTransform::new(Vector::new(i * 8, 0, 0), Rotation::identity()),
+///     );
+///     builder.set_layer(barrel, layer);
+/// }
+///
+/// let scene = builder.build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct SceneBuilder {
+    scene: Scene,
+
+    /// Maps node IDs to indices into `scene.nodes`.
+    index: HashMap<u32, usize>,
+
+    next_node_id: u32,
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneBuilder {
+    /// Creates a builder with a root transform and an empty root group.
+    pub fn new() -> Self {
+        let mut builder = Self {
+            scene: Scene::default(),
+            index: HashMap::new(),
+            next_node_id: ROOT_NODE_ID + 2,
+        };
+
+        let root_group_id = ROOT_NODE_ID + 1;
+        builder.push_node(Node::Transform(Transform {
+            node_id: ROOT_NODE_ID,
+            attributes: Attributes::default(),
+            child_node_id: root_group_id,
+            reserved_id: None,
+            layer_id: None,
+            frames: vec![Attributes::default()],
+        }));
+        builder.push_node(Node::Group(Group {
+            node_id: root_group_id,
+            attributes: Attributes::default(),
+            children: vec![],
+        }));
+
+        builder
+    }
+
+    /// Adds a layer and returns its ID.
+    pub fn add_layer(&mut self, name: impl Into<String>) -> u32 {
+        let layer_id = u32::try_from(self.scene.layers.len()).expect("int overflow");
+
+        let mut attributes = Attributes::default();
+        attributes.insert("_name", name);
+
+        self.scene.layers.push(Layer {
+            node_id: layer_id,
+            attributes,
+            reserved_id: None,
+        });
+
+        layer_id
+    }
+
+    /// Adds a group as child of the group `parent`, and returns the ID of the
+    /// group's transform.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not the transform of a group.
+    pub fn add_group(&mut self, parent: u32, transform: SceneTransform) -> u32 {
+        let transform_id = self.add_transform(parent, transform);
+        let group_id = self.allocate_node_id();
+
+        self.push_node(Node::Group(Group {
+            node_id: group_id,
+            attributes: Attributes::default(),
+            children: vec![],
+        }));
+
+        transform_id
+    }
+
+    /// Adds an instance of the model `model_id` as child of the group `parent`,
+    /// and returns the ID of the instance's transform.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not the transform of a group.
+    pub fn add_instance(&mut self, parent: u32, model_id: u32, transform: SceneTransform) -> u32 {
+        let transform_id = self.add_transform(parent, transform);
+        let shape_id = self.allocate_node_id();
+
+        self.push_node(Node::Shape(Shape {
+            node_id: shape_id,
+            attributes: Attributes::default(),
+            models: vec![ShapeModel {
+                model_id,
+                attributes: Attributes::default(),
+            }],
+        }));
+
+        transform_id
+    }
+
+    /// Sets the name (`_name` attribute) of a node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no transform with this ID.
+    pub fn set_name(&mut self, node_id: u32, name: impl Into<String>) {
+        self.transform_mut(node_id).attributes.insert("_name", name);
+    }
+
+    /// Sets whether a node is hidden (`_hidden` attribute).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no transform with this ID.
+    pub fn set_hidden(&mut self, node_id: u32, hidden: bool) {
+        let attributes = &mut self.transform_mut(node_id).attributes;
+        if hidden {
+            attributes.insert("_hidden", "1");
+        }
+        else {
+            attributes.remove("_hidden");
+        }
+    }
+
+    /// Puts a node on a layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no transform with this ID.
+    pub fn set_layer(&mut self, node_id: u32, layer_id: u32) {
+        self.transform_mut(node_id).layer_id = Some(layer_id);
+    }
+
+    /// Returns the built scene.
+    pub fn build(self) -> Scene {
+        self.scene
+    }
+
+    fn allocate_node_id(&mut self) -> u32 {
+        let node_id = self.next_node_id;
+        self.next_node_id = self.next_node_id.checked_add(1).expect("int overflow");
+        node_id
+    }
+
+    fn push_node(&mut self, node: Node) {
+        self.index.insert(node.node_id(), self.scene.nodes.len());
+        self.scene.nodes.push(node);
+    }
+
+    fn add_transform(&mut self, parent: u32, transform: SceneTransform) -> u32 {
+        let parent_group_id = self.transform_mut(parent).child_node_id;
+
+        // The child is allocated by the caller right after the transform.
+        let transform_id = self.allocate_node_id();
+        let child_node_id = transform_id + 1;
+
+        match self.node_mut(parent_group_id) {
+            Some(Node::Group(group)) => group.children.push(transform_id),
+            _ => panic!("Node {} is not the transform of a group", parent),
+        }
+
+        self.push_node(Node::Transform(Transform {
+            node_id: transform_id,
+            attributes: Attributes::default(),
+            child_node_id,
+            reserved_id: None,
+            layer_id: None,
+            frames: vec![transform.to_frame_attributes()],
+        }));
+
+        transform_id
+    }
+
+    fn node_mut(&mut self, node_id: u32) -> Option<&mut Node> {
+        let i = *self.index.get(&node_id)?;
+        Some(&mut self.scene.nodes[i])
+    }
+
+    fn transform_mut(&mut self, node_id: u32) -> &mut Transform {
+        match self.node_mut(node_id) {
+            Some(Node::Transform(transform)) => transform,
+            _ => panic!("Node {} is not a transform", node_id),
+        }
+    }
+}
+
+/// A model placed in the scene. This is created by [`Scene::instances`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Instance {
+    /// The index of the model.
+    pub model_id: u32,
+
+    /// The ID of the shape node that references the model. This is `None` for
+    /// files without a scene graph.
+    pub shape_node_id: Option<u32>,
+
+    /// The ID of the transform node that is the parent of the shape. This node
+    /// holds the instance's name. This is `None` for files without a scene
+    /// graph.
+    pub transform_node_id: Option<u32>,
+
+    /// The layer the instance is on.
+    pub layer_id: Option<u32>,
+
+    /// The world transform of the instance.
+    pub transform: SceneTransform,
+}
+
+/// An axis-aligned box of voxels. This is used in world-space for the scene
+/// graph, and in model-space for regions within a model.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct BoundingBox {
+    /// The minimum corner (inclusive).
+    pub min: Vector<i32>,
+
+    /// The maximum corner (exclusive).
+    pub max: Vector<i32>,
+}
+
+impl BoundingBox {
+    /// Returns the bounding box of a model of size `size` that is placed with
+    /// `transform`. Returns `None` if the model is empty. Voxels can't lie
+    /// beyond [`MAX_MODEL_SIZE`], so larger sizes only shift the pivot. See
+    /// [`SceneTransform::model_to_world`].
+    pub fn of_model(size: Size, transform: &SceneTransform) -> Option<Self> {
+        if size.x == 0 || size.y == 0 || size.z == 0 {
+            return None;
+        }
+
+        // Rotations only permute and mirror the axes, so the corner voxels are mapped
+        // to corner voxels. Points are stored as unsigned bytes.
+        let last = size.map(|x| (x.min(MAX_MODEL_SIZE) - 1) as u8 as i8);
+        let a = transform.model_to_world(Vector::default(), size);
+        let b = transform.model_to_world(last, size);
+
+        Some(Self {
+            min: Vector::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: Vector::new(a.x.max(b.x) + 1, a.y.max(b.y) + 1, a.z.max(b.z) + 1),
+        })
+    }
+
+    /// Returns the box covering a model of size `size` in model-space.
+    pub fn of_size(size: Size) -> Self {
+        Self {
+            min: Vector::default(),
+            max: size.map(|x| x.min(i32::MAX as u32) as i32),
+        }
+    }
+
+    /// Returns the size of the box.
+    pub fn size(&self) -> Vector<i32> {
+        self.max - self.min
+    }
+
+    /// Returns whether the box contains no voxels.
+    pub fn is_empty(&self) -> bool {
+        self.min.x >= self.max.x || self.min.y >= self.max.y || self.min.z >= self.max.z
+    }
+
+    /// Returns the intersection of both boxes, or `None` if they don't
+    /// overlap.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let intersection = Self {
+            min: Vector::new(
+                self.min.x.max(other.min.x),
+                self.min.y.max(other.min.y),
+                self.min.z.max(other.min.z),
+            ),
+            max: Vector::new(
+                self.max.x.min(other.max.x),
+                self.max.y.min(other.max.y),
+                self.max.z.min(other.max.z),
+            ),
+        };
+        (!intersection.is_empty()).then_some(intersection)
+    }
+
+    /// Returns an iterator over all voxel positions in the box, ordered by z,
+    /// then y, then x coordinate.
+    pub fn points(&self) -> impl Iterator<Item = Vector<i32>> {
+        let Self { min, max } = *self;
+        (min.z..max.z).flat_map(move |z| {
+            (min.y..max.y).flat_map(move |y| (min.x..max.x).map(move |x| Vector::new(x, y, z)))
+        })
+    }
+
+    /// Tests if the voxel at `point` is inside the box.
+    pub fn contains(&self, point: Vector<i32>) -> bool {
+        (self.min.x..self.max.x).contains(&point.x)
+            && (self.min.y..self.max.y).contains(&point.y)
+            && (self.min.z..self.max.z).contains(&point.z)
+    }
+}
+
+/// A voxel with world-space coordinates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct WorldVoxel {
+    pub point: Vector<i32>,
+    pub color_index: ColorIndex,
+}
+
+impl VoxData {
+    /// Returns every visible model instance at frame `0`. See
+    /// [`Scene::instances`].
+    pub fn instances(&self) -> Vec<Instance> {
+        self.scene.instances(self.models.len())
+    }
+
+    /// Returns every visible instance of the model `model_id` at frame `0`.
+    pub fn instances_of(&self, model_id: u32) -> Vec<Instance> {
+        self.instances()
+            .into_iter()
+            .filter(|instance| instance.model_id == model_id)
+            .collect()
+    }
+
+    /// Returns the world-space bounding box of an instance. Returns `None` if
+    /// the model doesn't exist or is empty.
+    pub fn instance_bounds(&self, instance: &Instance) -> Option<BoundingBox> {
+        let model = self.models.get(instance.model_id as usize)?;
+        BoundingBox::of_model(model.size, &instance.transform)
+    }
+
+    /// Flattens the scene into voxels with world-space coordinates. See
+    /// [`Scene::instances`] for how the scene graph is traversed, and
+    /// [`SceneTransform::model_to_world`] for how voxel coordinates are
+    /// transformed.
+    ///
+    /// Instances referencing models that don't exist are skipped.
+    ///
+    /// ```
+    /// # let vox_data = vox_format::from_file("../test_files/test_multiple_models.vox").unwrap();
+    /// for voxel in vox_data.world_voxels() {
+    ///     println!("{:?}: #{}", voxel.point, voxel.color_index);
+    /// }
+    /// ```
+    pub fn world_voxels(&self) -> WorldVoxels<'_> {
+        self.world_voxels_at(0)
+    }
+
+    /// Same as [`VoxData::world_voxels`], but evaluates the scene at `frame`.
+    pub fn world_voxels_at(&self, frame: u32) -> WorldVoxels<'_> {
+        WorldVoxels {
+            models: &self.models,
+            instances: self
+                .scene
+                .instances_at(self.models.len(), frame)
+                .into_iter(),
+            current: None,
+        }
+    }
+}
+
+/// An iterator over voxels in world-space. This is created with
+/// [`VoxData::world_voxels`] or [`VoxData::world_voxels_at`].
+#[derive(Debug)]
+pub struct WorldVoxels<'a> {
+    models: &'a [Model],
+    instances: std::vec::IntoIter<Instance>,
+    current: Option<(SceneTransform, Size, slice::Iter<'a, Voxel>)>,
+}
+
+impl<'a> Iterator for WorldVoxels<'a> {
+    type Item = WorldVoxel;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((transform, size, voxels)) = &mut self.current {
+                if let Some(voxel) = voxels.next() {
+                    return Some(WorldVoxel {
+                        point: transform.model_to_world(voxel.point, *size),
+                        color_index: voxel.color_index,
+                    });
+                }
+            }
+
+            let instance = self.instances.next()?;
+            if let Some(model) = self.models.get(instance.model_id as usize) {
+                self.current = Some((instance.transform, model.size, model.voxels.iter()));
+            }
+            else {
+                log::warn!("Scene references missing model {}", instance.model_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BoundingBox,
+        Node,
+        Scene,
+        SceneBuilder,
+        SceneError,
+        SceneTransform,
+        ROOT_NODE_ID,
+    };
+    use crate::{
+        data::VoxData,
+        reader::{
+            from_slice,
+            from_slice_with_options,
+            read_vox_into_with_options,
+            ReadOptions,
+        },
+        types::{
+            Attributes,
+            Group,
+            Model,
+            Rotation,
+            Shape,
+            ShapeModel,
+            Transform,
+            Vector,
+            Voxel,
+        },
+        writer::{
+            to_vec,
+            to_writer_with_options,
+            Error as WriteError,
+            WriteOptions,
+        },
+    };
+
+    #[test]
+    fn rotations_round_trip_through_bytes() {
+        for byte in 0..128 {
+            if let Some(rotation) = Rotation::from_byte(byte) {
+                assert_eq!(Rotation::from_matrix(rotation.matrix()), Some(rotation));
+                assert_eq!(rotation.compose(rotation.inverse()), Rotation::identity());
+            }
+        }
+    }
+
+    #[test]
+    fn it_rotates_around_the_model_center() {
+        // 180° around the z axis.
+        let rotation = Rotation::from_matrix([[-1, 0, 0], [0, -1, 0], [0, 0, 1]]).unwrap();
+        let transform = SceneTransform::new(Vector::new(10, 0, 0), rotation);
+        let size = Vector::new(4, 1, 1);
+
+        assert_eq!(
+            transform.model_to_world(Vector::new(0, 0, 0), size),
+            Vector::new(11, 0, 0)
+        );
+        assert_eq!(
+            transform.model_to_world(Vector::new(3, 0, 0), size),
+            Vector::new(8, 0, 0)
+        );
+    }
+
+    #[test]
+    fn it_flattens_multiple_models() {
+        let vox = from_slice(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_multiple_models.vox"
+        )))
+        .unwrap();
+
+        let instances = vox.scene.instances(vox.models.len());
+        assert_eq!(instances.len(), 2);
+
+        let mut voxels = vox
+            .world_voxels()
+            .map(|voxel| voxel.point)
+            .collect::<Vec<_>>();
+        voxels.sort();
+
+        let mut expected = vec![
+            // Model 0 (size 3x3x1) at (0, -2, 0)
+            Vector::new(-1, -1, 0),
+            Vector::new(0, -2, 0),
+            Vector::new(1, -2, 0),
+            Vector::new(0, -3, 0),
+            Vector::new(-1, -3, 0),
+            // Model 1 (size 3x1x3) at (0, 3, 1)
+            Vector::new(-1, 3, 1),
+            Vector::new(0, 3, 0),
+            Vector::new(1, 3, 0),
+            Vector::new(1, 3, 1),
+            Vector::new(1, 3, 2),
+        ];
+        expected.sort();
+
+        assert_eq!(voxels, expected);
+    }
+
+    #[test]
+    fn it_writes_built_scenes() {
+        let mut builder = SceneBuilder::new();
+        let layer = builder.add_layer("props");
+        let group = builder.add_group(
+            ROOT_NODE_ID,
+            SceneTransform::new(Vector::new(0, 0, 10), Rotation::identity()),
+        );
+        builder.set_name(group, "group");
+        let a = builder.add_instance(group, 0, SceneTransform::identity());
+        builder.set_layer(a, layer);
+        let b = builder.add_instance(
+            group,
+            0,
+            SceneTransform::new(
+                Vector::new(5, 0, 0),
+                Rotation::from_matrix([[-1, 0, 0], [0, -1, 0], [0, 0, 1]]).unwrap(),
+            ),
+        );
+        builder.set_hidden(b, true);
+        builder.add_instance(
+            group,
+            0,
+            SceneTransform::new(Vector::new(-5, 0, 0), Rotation::identity()),
+        );
+
+        let vox = VoxData {
+            models: vec![Model {
+                size: Vector::new(2, 1, 1),
+                voxels: vec![Voxel::new([0, 0, 0], 1), Voxel::new([1, 0, 0], 2)],
+            }],
+            scene: builder.build(),
+            ..Default::default()
+        };
+
+        let vox = from_slice(&to_vec(&vox).unwrap()).unwrap();
+        assert_eq!(vox.scene.layers.len(), 1);
+        assert_eq!(vox.scene.nodes.len(), 10);
+
+        let mut voxels = vox
+            .world_voxels()
+            .map(|voxel| (voxel.point, voxel.color_index.0))
+            .collect::<Vec<_>>();
+        voxels.sort();
+
+        assert_eq!(
+            voxels,
+            vec![
+                (Vector::new(-6, 0, 10), 1),
+                (Vector::new(-5, 0, 10), 2),
+                (Vector::new(-1, 0, 10), 1),
+                (Vector::new(0, 0, 10), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_scenes() {
+        let mut builder = SceneBuilder::new();
+        let layer = builder.add_layer("0");
+        let a = builder.add_instance(ROOT_NODE_ID, 0, SceneTransform::identity());
+        builder.set_layer(a, layer);
+        builder.add_instance(ROOT_NODE_ID, 1, SceneTransform::identity());
+        assert!(builder.clone().build().validate(2).is_empty());
+
+        builder.set_layer(a, 5);
+        let mut scene = builder.build();
+        scene.nodes.push(Node::Group(Group {
+            node_id: 7,
+            attributes: Attributes::default(),
+            children: vec![7, 8],
+        }));
+        scene.nodes.push(Node::Group(Group {
+            node_id: 7,
+            attributes: Attributes::default(),
+            children: vec![],
+        }));
+
+        let errors = scene.validate(1);
+        assert_eq!(
+            errors,
+            vec![
+                SceneError::DuplicateNodeId { node_id: 7 },
+                SceneError::MissingLayer {
+                    node_id: 2,
+                    layer_id: 5
+                },
+                SceneError::MissingModel {
+                    node_id: 5,
+                    model_id: 1
+                },
+                SceneError::InvalidChild {
+                    node_id: 7,
+                    child_node_id: 7
+                },
+                SceneError::DanglingChild {
+                    node_id: 7,
+                    child_node_id: 8
+                },
+                SceneError::Cycle { node_id: 7 },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_refuses_to_write_invalid_scenes() {
+        let mut scene = SceneBuilder::new().build();
+        scene.nodes.remove(1);
+
+        let vox = VoxData {
+            scene,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            to_vec(&vox),
+            Err(WriteError::InvalidScene(errors)) if errors == vec![SceneError::DanglingChild { node_id: 0, child_node_id: 1 }]
+        ));
+    }
+
+    #[test]
+    fn it_validates_scenes_while_reading() {
+        let mut vox = VoxData::default();
+        read_vox_into_with_options(
+            std::io::Cursor::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_multiple_models.vox"
+            ))),
+            &mut vox,
+            &ReadOptions {
+                validate_scene: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(vox.scene.nodes.len(), 6);
+    }
+
+    #[test]
+    fn it_walks_deep_scenes_without_recursion() {
+        // Transforms have even IDs, and groups odd IDs.
+        let depth = 20_000;
+        let mut nodes = vec![];
+        for i in 0..depth {
+            nodes.push(Node::Transform(Transform {
+                node_id: 2 * i,
+                attributes: Attributes::default(),
+                child_node_id: 2 * i + 1,
+                reserved_id: None,
+                layer_id: None,
+                frames: vec![Attributes::default()],
+            }));
+            nodes.push(Node::Group(Group {
+                node_id: 2 * i + 1,
+                attributes: Attributes::default(),
+                children: vec![2 * i + 2],
+            }));
+        }
+        nodes.push(Node::Transform(Transform {
+            node_id: 2 * depth,
+            attributes: Attributes::default(),
+            child_node_id: 2 * depth + 1,
+            reserved_id: None,
+            layer_id: None,
+            frames: vec![Attributes::default()],
+        }));
+        nodes.push(Node::Shape(Shape {
+            node_id: 2 * depth + 1,
+            attributes: Attributes::default(),
+            models: vec![ShapeModel {
+                model_id: 0,
+                attributes: Attributes::default(),
+            }],
+        }));
+
+        let mut scene = Scene {
+            nodes,
+            layers: vec![],
+        };
+        assert!(scene.validate(1).is_empty());
+        assert_eq!(scene.instances(1).len(), 1);
+
+        // Close the chain into a cycle.
+        if let Some(Node::Group(group)) = scene.nodes.get_mut(2 * depth as usize - 1) {
+            group.children = vec![0];
+        }
+        assert!(scene
+            .validate(1)
+            .contains(&SceneError::Cycle { node_id: 0 }));
+        assert!(scene.instances(1).is_empty());
+    }
+
+    #[test]
+    fn it_displays_lists_of_scene_errors() {
+        let error = |n| WriteError::InvalidScene(vec![SceneError::MissingRoot; n]).to_string();
+        assert_eq!(error(0), "Invalid scene graph: no errors");
+        assert_eq!(
+            error(1),
+            format!("Invalid scene graph: {}", SceneError::MissingRoot)
+        );
+        assert!(error(3).ends_with(" (and 2 more)"));
+    }
+
+    #[test]
+    fn it_queries_scenes() {
+        let vox = from_slice(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_multiple_models.vox"
+        )))
+        .unwrap();
+
+        let instances = vox.instances_of(1);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].transform_node_id, Some(4));
+        assert_eq!(instances[0].layer_id, Some(0));
+        assert_eq!(
+            vox.instance_bounds(&instances[0]),
+            Some(BoundingBox {
+                min: Vector::new(-1, 3, 0),
+                max: Vector::new(2, 4, 3),
+            })
+        );
+
+        let members = vox
+            .scene
+            .layer_members(0)
+            .iter()
+            .map(|transform| transform.node_id)
+            .collect::<Vec<_>>();
+        assert_eq!(members, vec![2, 4]);
+
+        assert_eq!(
+            vox.scene.world_transform(3),
+            Some(SceneTransform::new(
+                Vector::new(0, -2, 0),
+                Rotation::identity()
+            ))
+        );
+    }
+
+    #[test]
+    fn it_bounds_oversized_and_empty_models() {
+        let transform = SceneTransform::identity();
+        assert_eq!(
+            BoundingBox::of_model(Vector::new(300, 1, 1), &transform),
+            Some(BoundingBox {
+                min: Vector::new(-150, 0, 0),
+                max: Vector::new(106, 1, 1),
+            })
+        );
+        assert_eq!(
+            BoundingBox::of_model(Vector::new(0, 1, 1), &transform),
+            None
+        );
+    }
+
+    #[test]
+    fn it_finds_nodes_by_name() {
+        let mut builder = SceneBuilder::new();
+        let group = builder.add_group(
+            ROOT_NODE_ID,
+            SceneTransform::new(Vector::new(1, 2, 3), Rotation::identity()),
+        );
+        let spawn = builder.add_instance(
+            group,
+            0,
+            SceneTransform::new(Vector::new(10, 0, 0), Rotation::identity()),
+        );
+        builder.set_name(spawn, "spawn");
+        let scene = builder.build();
+
+        let nodes = scene.find_by_name("spawn");
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].node_id(), spawn);
+        assert_eq!(
+            scene.world_transform(spawn).unwrap().translation,
+            Vector::new(11, 2, 3)
+        );
+        assert!(scene.find_by_name("trigger").is_empty());
+    }
+
+    #[test]
+    fn it_upgrades_legacy_files() {
+        let legacy = VoxData {
+            models: vec![
+                Model {
+                    size: Vector::new(1, 1, 1),
+                    voxels: vec![Voxel::new([0, 0, 0], 1)],
+                },
+                Model {
+                    size: Vector::new(3, 2, 1),
+                    voxels: vec![Voxel::new([2, 1, 0], 2)],
+                },
+            ],
+            ..Default::default()
+        };
+
+        // Without a scene graph, this is written with a `PACK` chunk.
+        let read_options = ReadOptions {
+            record_layout: true,
+            ..Default::default()
+        };
+        let mut vox = from_slice_with_options(&to_vec(&legacy).unwrap(), &read_options).unwrap();
+        assert!(vox.layout.has_pack());
+        assert!(vox.scene.is_empty());
+
+        assert!(vox.upgrade());
+        assert!(!vox.upgrade());
+        assert!(!vox.layout.has_pack());
+        assert!(vox.scene.validate(2).is_empty());
+
+        let options = WriteOptions {
+            preserve_layout: true,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        to_writer_with_options(&mut buf, &vox, &options).unwrap();
+
+        let upgraded = from_slice_with_options(&buf, &read_options).unwrap();
+        assert!(!upgraded.layout.has_pack());
+        assert_eq!(upgraded.scene.nodes.len(), 6);
+        assert_eq!(
+            upgraded
+                .instances()
+                .iter()
+                .map(|instance| instance.model_id)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+
+        // The models are shown the same way as before the upgrade.
+        assert_eq!(
+            upgraded.world_voxels().collect::<Vec<_>>(),
+            legacy.world_voxels().collect::<Vec<_>>()
+        );
+
+        // Without a preserved layout, PACK is not written either.
+        let vox = from_slice_with_options(&to_vec(&vox).unwrap(), &read_options).unwrap();
+        assert!(!vox.layout.has_pack());
+    }
+}
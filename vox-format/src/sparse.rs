@@ -0,0 +1,342 @@
+//! A sparse voxel model, that only stores filled cells.
+//!
+//! [`SparseModel`] stores voxels in a `BTreeMap` keyed by their point, so it's
+//! best suited for large models that are mostly empty. For models that are
+//! mostly filled, [`crate::dense::DenseModel`] uses less memory.
+
+use std::collections::{
+    btree_map,
+    BTreeMap,
+};
+
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    data::{
+        VoxModelBuffer,
+        VoxModelSource,
+    },
+    dense::DenseModel,
+    types::{
+        ColorIndex,
+        Model,
+        Palette,
+        Point,
+        Size,
+        Vector,
+        Voxel,
+    },
+};
+
+/// A model that stores its voxels in a `BTreeMap` keyed by their point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SparseModel {
+    size: Size,
+    voxels: BTreeMap<Key, ColorIndex>,
+}
+
+/// The key of a voxel in a [`SparseModel`]. Keys are ordered by z, then y,
+/// then x coordinate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+struct Key {
+    z: u8,
+    y: u8,
+    x: u8,
+}
+
+impl From<Point> for Key {
+    fn from(point: Point) -> Self {
+        // Points are stored as unsigned bytes.
+        Self {
+            z: point.z as u8,
+            y: point.y as u8,
+            x: point.x as u8,
+        }
+    }
+}
+
+impl From<Key> for Point {
+    fn from(key: Key) -> Self {
+        Vector::new(key.x as i8, key.y as i8, key.z as i8)
+    }
+}
+
+impl SparseModel {
+    /// Creates an empty model with the given size.
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            voxels: BTreeMap::new(),
+        }
+    }
+
+    /// The size of the model.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The number of voxels.
+    pub fn num_voxels(&self) -> usize {
+        self.voxels.len()
+    }
+
+    /// Returns whether there are no voxels.
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    /// Returns whether `point` lies within the model.
+    pub fn contains(&self, point: impl Into<Point>) -> bool {
+        // Points are stored as unsigned bytes.
+        let point = point.into().map(|p| u32::from(p as u8));
+        point.x < self.size.x && point.y < self.size.y && point.z < self.size.z
+    }
+
+    /// Returns the color index at `point`, or `None` if there is no voxel.
+    pub fn get(&self, point: impl Into<Point>) -> Option<ColorIndex> {
+        self.voxels.get(&Key::from(point.into())).copied()
+    }
+
+    /// Inserts a voxel at `point` and returns the previous color index.
+    /// Inserting color index 0 removes the voxel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `point` is out of bounds.
+    pub fn insert(
+        &mut self,
+        point: impl Into<Point>,
+        color_index: impl Into<ColorIndex>,
+    ) -> Option<ColorIndex> {
+        let point = point.into();
+        let color_index = color_index.into();
+
+        if !self.contains(point) {
+            panic!("Point {:?} out of bounds {:?}", point, self.size);
+        }
+
+        if color_index.0 == 0 {
+            self.voxels.remove(&point.into())
+        }
+        else {
+            self.voxels.insert(point.into(), color_index)
+        }
+    }
+
+    /// Removes the voxel at `point` and returns its color index.
+    pub fn remove(&mut self, point: impl Into<Point>) -> Option<ColorIndex> {
+        self.voxels.remove(&Key::from(point.into()))
+    }
+
+    /// Removes all voxels.
+    pub fn clear(&mut self) {
+        self.voxels.clear();
+    }
+
+    /// Returns an iterator over all voxels. The voxels are ordered by z, then
+    /// y, then x coordinate, like [`DenseModel::voxels`].
+    pub fn voxels(&self) -> SparseVoxels<'_> {
+        SparseVoxels {
+            inner: self.voxels.iter(),
+        }
+    }
+}
+
+/// Iterator over the voxels of a [`SparseModel`].
+#[derive(Clone, Debug)]
+pub struct SparseVoxels<'a> {
+    inner: btree_map::Iter<'a, Key, ColorIndex>,
+}
+
+impl Iterator for SparseVoxels<'_> {
+    type Item = Voxel;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, color_index) = self.inner.next()?;
+        Some(Voxel::new(Point::from(*key), *color_index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for SparseVoxels<'_> {}
+
+impl VoxModelBuffer for SparseModel {
+    fn new(size: Size) -> Self {
+        SparseModel::new(size)
+    }
+
+    fn set_voxel(&mut self, voxel: Voxel, _palette: &Palette) {
+        if self.contains(voxel.point) {
+            self.insert(voxel.point, voxel.color_index);
+        }
+        else {
+            log::warn!("Ignoring voxel out of bounds: {:?}", voxel);
+        }
+    }
+}
+
+impl VoxModelSource for SparseModel {
+    type Voxels<'a> = SparseVoxels<'a>;
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn num_voxels(&self) -> usize {
+        self.voxels.len()
+    }
+
+    fn voxels(&self) -> Self::Voxels<'_> {
+        SparseModel::voxels(self)
+    }
+}
+
+/// Converts a [`Model`] into a sparse model. Voxels that are out of bounds are
+/// ignored, and later voxels replace earlier ones at the same point.
+impl From<&Model> for SparseModel {
+    fn from(model: &Model) -> Self {
+        let mut sparse = SparseModel::new(model.size);
+        for voxel in &model.voxels {
+            if sparse.contains(voxel.point) {
+                sparse.insert(voxel.point, voxel.color_index);
+            }
+        }
+        sparse
+    }
+}
+
+impl From<Model> for SparseModel {
+    fn from(model: Model) -> Self {
+        Self::from(&model)
+    }
+}
+
+/// Converts a sparse model into a [`Model`], with the voxels in the order of
+/// [`SparseModel::voxels`].
+impl From<&SparseModel> for Model {
+    fn from(sparse: &SparseModel) -> Self {
+        Model {
+            size: sparse.size,
+            voxels: sparse.voxels().collect(),
+        }
+    }
+}
+
+impl From<SparseModel> for Model {
+    fn from(sparse: SparseModel) -> Self {
+        Self::from(&sparse)
+    }
+}
+
+impl From<&DenseModel> for SparseModel {
+    fn from(dense: &DenseModel) -> Self {
+        Self {
+            size: dense.size(),
+            voxels: dense
+                .voxels()
+                .map(|voxel| (voxel.point.into(), voxel.color_index))
+                .collect(),
+        }
+    }
+}
+
+impl From<DenseModel> for SparseModel {
+    fn from(dense: DenseModel) -> Self {
+        Self::from(&dense)
+    }
+}
+
+impl From<&SparseModel> for DenseModel {
+    fn from(sparse: &SparseModel) -> Self {
+        let mut dense = DenseModel::new(sparse.size);
+        for voxel in sparse.voxels() {
+            dense.set(voxel.point, voxel.color_index);
+        }
+        dense
+    }
+}
+
+impl From<SparseModel> for DenseModel {
+    fn from(sparse: SparseModel) -> Self {
+        Self::from(&sparse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SparseModel;
+    use crate::{
+        dense::DenseModel,
+        types::{
+            ColorIndex,
+            Model,
+            Vector,
+            Voxel,
+        },
+    };
+
+    #[test]
+    fn it_inserts_and_removes_voxels() {
+        let mut model = SparseModel::new(Vector::new(256, 256, 256));
+        assert!(model.is_empty());
+
+        assert_eq!(model.insert([-1, 2, 3], 5), None);
+        assert_eq!(model.insert([-1, 2, 3], 6), Some(ColorIndex(5)));
+        assert_eq!(model.insert([0, 1, 0], 7), None);
+        assert_eq!(model.insert([1, 1, 0], 8), None);
+        assert_eq!(model.num_voxels(), 3);
+
+        assert_eq!(model.get([-1, 2, 3]), Some(ColorIndex(6)));
+        assert_eq!(model.get([0, 0, 0]), None);
+
+        assert_eq!(model.insert([1, 1, 0], 0), Some(ColorIndex(8)));
+        assert_eq!(model.remove([0, 1, 0]), Some(ColorIndex(7)));
+        assert_eq!(model.remove([0, 1, 0]), None);
+        assert_eq!(model.num_voxels(), 1);
+    }
+
+    #[test]
+    fn it_iterates_in_order() {
+        let voxels = vec![
+            Voxel::new([0, 0, 0], 1),
+            Voxel::new([1, 0, 0], 2),
+            Voxel::new([-1, 0, 0], 3),
+            Voxel::new([0, 1, 0], 4),
+            Voxel::new([0, 0, 1], 5),
+        ];
+        let model = Model {
+            size: Vector::new(256, 2, 2),
+            voxels: voxels.iter().rev().copied().collect(),
+        };
+
+        let sparse = SparseModel::from(&model);
+        assert_eq!(sparse.voxels().collect::<Vec<_>>(), voxels);
+        assert_eq!(Model::from(&sparse).voxels, voxels);
+    }
+
+    #[test]
+    fn it_converts_dense_models() {
+        let mut dense = DenseModel::new(Vector::new(4, 4, 4));
+        dense.set([1, 2, 3], 1);
+        dense.set([3, 0, 0], 2);
+
+        let sparse = SparseModel::from(&dense);
+        assert_eq!(sparse.num_voxels(), 2);
+        assert_eq!(sparse.get([3, 0, 0]), Some(ColorIndex(2)));
+        assert_eq!(
+            sparse.voxels().collect::<Vec<_>>(),
+            dense.voxels().collect::<Vec<_>>()
+        );
+
+        assert_eq!(DenseModel::from(&sparse), dense);
+    }
+}
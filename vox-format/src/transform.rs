@@ -0,0 +1,230 @@
+//! Geometric transforms of whole models.
+//!
+//! These functions work on any [`EditModel`] and return a new model with a
+//! consistent size. Rotations use [`Rotation`], which is also used for the
+//! `_r` attribute of transform nodes, so any rotation from a scene graph can
+//! be applied to a model directly. Voxels that are moved out of the model are
+//! clipped.
+
+use crate::{
+    edit::{
+        model_to_point,
+        point_to_model,
+        EditModel,
+    },
+    scene::BoundingBox,
+    types::{
+        Axis,
+        Rotation,
+        Size,
+        Vector,
+        Voxel,
+    },
+    validate::MAX_MODEL_SIZE,
+};
+
+/// Rotates (or mirrors) a model about its center. The size of the model is
+/// rotated too, so no voxels are clipped.
+///
+/// This places voxels in the same cells as MagicaVoxel does, when the model is
+/// placed in a scene with `rotation`. See
+/// [`crate::scene::SceneTransform::model_to_world`].
+pub fn rotate<M: EditModel>(model: &M, rotation: Rotation) -> M {
+    let size = model.size().map(|x| x.min(MAX_MODEL_SIZE) as i32);
+    let rotated_size = rotation.rotate(size).map(i32::abs);
+
+    let mut rotated = M::new(rotated_size.map(|x| x as u32));
+    rotated.set_all(model.voxels().map(|voxel| {
+        // Rotate the voxel center relative to the model center, scaled by 2 to
+        // stay in integers.
+        let center = point_to_model(voxel.point).map(|x| 2 * x + 1) - size;
+        let point = (rotation.rotate(center) + rotated_size).map(|x| (x - 1) / 2);
+
+        Voxel::new(model_to_point(point), voxel.color_index)
+    }));
+    rotated
+}
+
+/// Rotates a model by `quarter_turns` times 90° about `axis`. See
+/// [`Rotation::quarter_turns`].
+pub fn rotate_quarter_turns<M: EditModel>(model: &M, axis: Axis, quarter_turns: i32) -> M {
+    rotate(model, Rotation::quarter_turns(axis, quarter_turns))
+}
+
+/// Mirrors a model along `axis`.
+pub fn mirror<M: EditModel>(model: &M, axis: Axis) -> M {
+    rotate(model, Rotation::mirror(axis))
+}
+
+/// Moves all voxels by `offset`. Voxels that are moved out of the model are
+/// clipped.
+pub fn translate<M: EditModel>(model: &M, offset: Vector<i32>) -> M {
+    resize(model, model.size(), offset)
+}
+
+/// Crops a model to `region`. The cropped model has the size of `region`
+/// clipped to the model, and its voxels are moved by `-region.min`.
+pub fn crop<M: EditModel>(model: &M, region: BoundingBox) -> M {
+    match region.intersection(&BoundingBox::of_size(model.size())) {
+        Some(region) => {
+            resize(
+                model,
+                region.size().map(|x| x as u32),
+                region.min.map(|x| -x),
+            )
+        }
+        None => M::new(Vector::default()),
+    }
+}
+
+/// Adds `before` empty cells before and `after` empty cells after the voxels
+/// along each axis.
+pub fn pad<M: EditModel>(model: &M, before: Size, after: Size) -> M {
+    let size = model.size();
+    resize(
+        model,
+        Vector::new(
+            size.x + before.x + after.x,
+            size.y + before.y + after.y,
+            size.z + before.z + after.z,
+        ),
+        before.map(|x| x as i32),
+    )
+}
+
+/// Changes the size of a model and moves all voxels by `offset`. Voxels that
+/// don't fit into the new size are clipped.
+pub fn resize<M: EditModel>(model: &M, size: Size, offset: Vector<i32>) -> M {
+    let bounds = BoundingBox::of_size(size.map(|x| x.min(MAX_MODEL_SIZE)));
+
+    let mut resized = M::new(size);
+    resized.set_all(model.voxels().filter_map(|voxel| {
+        let point = point_to_model(voxel.point) + offset;
+        bounds
+            .contains(point)
+            .then(|| Voxel::new(model_to_point(point), voxel.color_index))
+    }));
+    resized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        crop,
+        mirror,
+        pad,
+        rotate,
+        rotate_quarter_turns,
+        translate,
+    };
+    use crate::{
+        dense::DenseModel,
+        edit::EditModel,
+        scene::{
+            BoundingBox,
+            SceneTransform,
+        },
+        types::{
+            Axis,
+            ColorIndex,
+            Model,
+            Rotation,
+            Vector,
+            Voxel,
+        },
+    };
+
+    fn model() -> Model {
+        Model {
+            size: Vector::new(3, 2, 1),
+            voxels: vec![Voxel::new([0, 0, 0], 1), Voxel::new([2, 1, 0], 2)],
+        }
+    }
+
+    #[test]
+    fn it_creates_rotations() {
+        let v = Vector::new(1, 2, 3);
+        let rotate = |axis, n| Rotation::quarter_turns(axis, n).rotate(v);
+
+        assert_eq!(rotate(Axis::X, 1), Vector::new(1, -3, 2));
+        assert_eq!(rotate(Axis::Y, 1), Vector::new(3, 2, -1));
+        assert_eq!(rotate(Axis::Z, 1), Vector::new(-2, 1, 3));
+        assert_eq!(rotate(Axis::Z, -1), rotate(Axis::Z, 3));
+        assert_eq!(rotate(Axis::Z, 4), v);
+        assert_eq!(Rotation::mirror(Axis::Y).rotate(v), Vector::new(1, -2, 3));
+    }
+
+    #[test]
+    fn it_rotates_models() {
+        let rotated = rotate_quarter_turns(&model(), Axis::Z, 1);
+        assert_eq!(rotated.size, Vector::new(2, 3, 1));
+        assert_eq!(rotated.get([1, 0, 0].into()), Some(ColorIndex(1)));
+        assert_eq!(rotated.get([0, 2, 0].into()), Some(ColorIndex(2)));
+
+        let back = rotate_quarter_turns(&rotated, Axis::Z, -1);
+        assert_eq!(back, model());
+    }
+
+    #[test]
+    fn it_rotates_like_the_scene_graph() {
+        let model = Model {
+            size: Vector::new(4, 3, 2),
+            voxels: vec![
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([3, 1, 0], 2),
+                Voxel::new([1, 2, 1], 3),
+            ],
+        };
+
+        for byte in 0..0x80 {
+            let rotation = match Rotation::from_byte(byte) {
+                Some(rotation) => rotation,
+                None => continue,
+            };
+            let transform = SceneTransform {
+                rotation,
+                ..SceneTransform::identity()
+            };
+
+            let rotated = rotate(&model, rotation);
+            let origin = BoundingBox::of_model(model.size, &transform).unwrap().min;
+
+            for voxel in &model.voxels {
+                let world = transform.model_to_world(voxel.point, model.size) - origin;
+                assert_eq!(rotated.get(world.map(|x| x as i8)), Some(voxel.color_index));
+            }
+        }
+    }
+
+    #[test]
+    fn it_mirrors_models() {
+        let mirrored = mirror(&model(), Axis::X);
+        assert_eq!(mirrored.size, Vector::new(3, 2, 1));
+        assert_eq!(mirrored.get([2, 0, 0].into()), Some(ColorIndex(1)));
+        assert_eq!(mirrored.get([0, 1, 0].into()), Some(ColorIndex(2)));
+    }
+
+    #[test]
+    fn it_translates_crops_and_pads_models() {
+        let model = DenseModel::from(model());
+
+        let translated = translate(&model, Vector::new(1, 0, 0));
+        assert_eq!(translated.num_voxels(), 1);
+        assert_eq!(translated.get([1, 0, 0]), Some(ColorIndex(1)));
+
+        let cropped = crop(
+            &model,
+            BoundingBox {
+                min: Vector::new(1, 1, -5),
+                max: Vector::new(10, 10, 10),
+            },
+        );
+        assert_eq!(cropped.size(), Vector::new(2, 1, 1));
+        assert_eq!(cropped.get([1, 0, 0]), Some(ColorIndex(2)));
+
+        let padded = pad(&model, Vector::new(1, 0, 2), Vector::new(0, 3, 0));
+        assert_eq!(padded.size(), Vector::new(4, 5, 3));
+        assert_eq!(padded.get([1, 0, 2]), Some(ColorIndex(1)));
+        assert_eq!(padded.get([3, 1, 2]), Some(ColorIndex(2)));
+    }
+}
@@ -1,7 +1,7 @@
 //! Basic types
 
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     convert::{
         TryFrom,
         TryInto,
@@ -11,7 +11,13 @@ use std::{
         Read,
         Write,
     },
-    ops::Index,
+    iter::FromIterator,
+    ops::{
+        Add,
+        Index,
+        Sub,
+    },
+    str::FromStr,
 };
 
 use byteorder::{
@@ -34,6 +40,16 @@ use crate::{
 
 /// The version of a `.VOX` file. This is a wrapper around a `u32` and
 /// implements `Default` and the [`Version::is_supported`] method.
+///
+/// The known versions are:
+///
+///  - [`Version::V150`]: Written by MagicaVoxel up to 0.99.6. Old files store
+///    the number of models in a `PACK` chunk, newer ones have a scene graph
+///    (`nTRN`, `nGRP` and `nSHP` chunks), layers and materials.
+///  - [`Version::V200`]: Written since MagicaVoxel 0.99.7. The chunks are the
+///    same, but files may contain animations (`_f` frame attributes on
+///    transform and shape nodes) and additional chunks like `NOTE` and `IMAP`,
+///    which are kept as raw chunks.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(
     feature = "serialize",
@@ -43,18 +59,54 @@ use crate::{
 pub struct Version(pub u32);
 
 impl Version {
-    /// Returns whether this version is supported.
+    /// Version 150.
+    pub const V150: Self = Self(150);
+
+    /// Version 200.
+    pub const V200: Self = Self(200);
+
+    /// All versions this crate knows about, in ascending order.
+    pub const KNOWN: &'static [Self] = &[Self::V150, Self::V200];
+
+    /// Returns whether this is one of the [`Version::KNOWN`] versions.
     pub fn is_supported(&self) -> bool {
-        *self == Self::default()
+        Self::KNOWN.contains(self)
+    }
+
+    /// Returns whether this version is accepted by `policy`.
+    pub fn is_accepted_by(&self, policy: VersionPolicy) -> bool {
+        match policy {
+            VersionPolicy::Strict => self.is_supported(),
+            VersionPolicy::Permissive => true,
+        }
+    }
+
+    /// Returns whether files of this version may contain animations.
+    pub fn has_animation(&self) -> bool {
+        *self >= Self::V200
     }
 }
 
+/// Returns version 150, since it can be read by all versions of MagicaVoxel.
 impl Default for Version {
     fn default() -> Self {
-        Self(150)
+        Self::V150
     }
 }
 
+/// Which file versions are accepted when reading or writing.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum VersionPolicy {
+    /// Only accept [`Version::KNOWN`] versions.
+    #[default]
+    Strict,
+
+    /// Accept any version. Unknown versions are treated like the latest known
+    /// version, which may fail if the format changed.
+    Permissive,
+}
+
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
@@ -74,14 +126,14 @@ impl Version {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 /// A collection of voxels with a specific size.
 ///
 /// Note, that the voxels are
 /// stored as a `Vec`, like they're stored in-file. Therefore there is no
 /// efficient point-query for voxels. If you need your models to support
-/// point-queries, you must implement your own [`crate::data::VoxBuffer`] or
+/// point-queries, use [`crate::dense::DenseModel`] or implement your own
 /// [`crate::data::VoxModelBuffer`]. For testing and convienience there is
 /// [`Model::get_voxel`] to perform a point-query with a linear search.
 pub struct Model {
@@ -153,6 +205,31 @@ impl<T> Vector<T> {
     pub fn new(x: T, y: T, z: T) -> Self {
         Self { x, y, z }
     }
+
+    /// Applies `f` to each component of the vector.
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> Vector<U> {
+        Vector {
+            x: f(self.x),
+            y: f(self.y),
+            z: f(self.z),
+        }
+    }
+}
+
+impl<T: Add<Output = T>> Add for Vector<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for Vector<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
 }
 
 impl Vector<i8> {
@@ -215,6 +292,156 @@ impl<T: fmt::Debug> fmt::Debug for Vector<T> {
 pub type Point = Vector<i8>;
 pub type Size = Vector<u32>;
 
+/// A rotation as it is stored in the `_r` attribute of [`Transform`] frames.
+///
+/// MagicaVoxel only supports rotations by multiples of 90°, optionally combined
+/// with mirroring. These are stored as a signed permutation matrix, which is
+/// packed into a single byte:
+///
+/// | bit | value                                          |
+/// |-----|------------------------------------------------|
+/// | 0-1 | index of the non-zero entry in the first row   |
+/// | 2-3 | index of the non-zero entry in the second row  |
+/// | 4   | sign of the first row (`1` means negative)     |
+/// | 5   | sign of the second row                         |
+/// | 6   | sign of the third row                          |
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(transparent)
+)]
+pub struct Rotation(u8);
+
+impl Rotation {
+    /// The identity rotation.
+    pub fn identity() -> Self {
+        Self(0b0000100)
+    }
+
+    /// Creates a rotation from its packed byte representation. Returns `None`
+    /// if the byte doesn't encode a valid signed permutation matrix.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        let i0 = byte & 3;
+        let i1 = (byte >> 2) & 3;
+        (i0 < 3 && i1 < 3 && i0 != i1 && byte & 0x80 == 0).then_some(Self(byte))
+    }
+
+    /// Returns the rotation by `quarter_turns` times 90° about `axis`. Positive
+    /// turns are counter-clockwise when looking from the positive axis
+    /// towards the origin.
+    pub fn quarter_turns(axis: Axis, quarter_turns: i32) -> Self {
+        let turn = Self::from_matrix(match axis {
+            Axis::X => [[1, 0, 0], [0, 0, -1], [0, 1, 0]],
+            Axis::Y => [[0, 0, 1], [0, 1, 0], [-1, 0, 0]],
+            Axis::Z => [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+        })
+        .expect("valid rotation matrix");
+
+        (0..quarter_turns.rem_euclid(4))
+            .fold(Self::identity(), |rotation, _| turn.compose(rotation))
+    }
+
+    /// Returns the reflection that mirrors along `axis`.
+    pub fn mirror(axis: Axis) -> Self {
+        let mut matrix = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+        matrix[axis as usize][axis as usize] = -1;
+        Self::from_matrix(matrix).expect("valid rotation matrix")
+    }
+
+    /// Returns the packed byte representation of the rotation.
+    pub fn to_byte(&self) -> u8 {
+        self.0
+    }
+
+    /// Creates a rotation from a matrix. Returns `None` if the matrix is not a
+    /// signed permutation matrix.
+    pub fn from_matrix(matrix: [[i32; 3]; 3]) -> Option<Self> {
+        let mut byte = 0;
+        let mut used = [false; 3];
+
+        for (i, row) in matrix.iter().enumerate() {
+            let mut non_zero = row.iter().enumerate().filter(|(_, x)| **x != 0);
+            let (j, sign) = non_zero.next()?;
+            if non_zero.next().is_some() || sign.abs() != 1 || used[j] {
+                return None;
+            }
+            used[j] = true;
+
+            if i < 2 {
+                byte |= (j as u8) << (2 * i);
+            }
+            if *sign < 0 {
+                byte |= 1 << (4 + i);
+            }
+        }
+
+        Some(Self(byte))
+    }
+
+    /// Returns the rotation as a matrix, which is to be multiplied with column
+    /// vectors.
+    pub fn matrix(&self) -> [[i32; 3]; 3] {
+        let i0 = usize::from(self.0 & 3);
+        let i1 = usize::from((self.0 >> 2) & 3);
+        let indices = [i0, i1, 3 - i0 - i1];
+
+        let mut matrix = [[0; 3]; 3];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[indices[i]] = if self.0 & (1 << (4 + i)) != 0 { -1 } else { 1 };
+        }
+        matrix
+    }
+
+    /// Rotates a vector.
+    pub fn rotate(&self, v: Vector<i32>) -> Vector<i32> {
+        let m = self.matrix();
+        let v = [v.x, v.y, v.z];
+        let row = |i: usize| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2];
+        Vector::new(row(0), row(1), row(2))
+    }
+
+    /// Returns the rotation that first applies `other` and then `self`.
+    pub fn compose(&self, other: Rotation) -> Rotation {
+        let a = self.matrix();
+        let b = other.matrix();
+        let mut m = [[0; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+        Self::from_matrix(m).expect("product of signed permutation matrices")
+    }
+
+    /// Returns the inverse rotation.
+    pub fn inverse(&self) -> Rotation {
+        let m = self.matrix();
+        let mut t = [[0; 3]; 3];
+        for (i, row) in t.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = m[j][i];
+            }
+        }
+        Self::from_matrix(t).expect("transpose of signed permutation matrix")
+    }
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+/// A coordinate axis.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Axis {
+    X = 0,
+    Y = 1,
+    Z = 2,
+}
+
 /// A color palette. This contains colors indexec by `u8`. It is used to look up
 /// colors of a voxel.
 ///
@@ -230,7 +457,8 @@ pub struct Palette {
     /// The colors of the palette.
     ///
     /// MagicaVoxel always set color 0 to be fully transparent. We don't enfore
-    /// this, but this palette entry will never be written to a `.VOX` file.
+    /// this. Color 0 is stored in the last, otherwise unused, entry of the
+    /// `RGBA` chunk.
     #[cfg_attr(feature = "serialize", serde(with = "serde_big_array::BigArray"))]
     pub colors: [Color; 256],
 }
@@ -261,32 +489,35 @@ impl Palette {
     ///     println!("{} -> {:?}", index, color);
     /// }
     /// ```
-    pub fn iter(&self) -> PaletteIter {
+    pub fn iter(&self) -> PaletteIter<'_> {
         PaletteIter {
             inner: self.colors.iter().enumerate(),
         }
     }
 
     /// Reads a color palette from a [`std::io::Read`].
+    ///
+    /// The `RGBA` chunk stores colors 1 to 255 first. The last entry is unused
+    /// by MagicaVoxel, but it's kept as color 0, like material 256 is the
+    /// material for color 0.
     pub fn read<R: Read>(mut reader: R) -> Result<Self, ReadError> {
         let mut palette = Palette::default();
 
         for i in 0..255 {
             palette.colors[i + 1] = Color::read(&mut reader)?;
         }
+        palette.colors[0] = Color::read(&mut reader)?;
 
         Ok(palette)
     }
 
-    /// Writes the color palette to a [`std::io::Write`].
-    ///
-    /// MagicaVoxel fixes color 0 to be always fully transparent, and doesn't
-    /// include it in the file. Therefore this method will ignore the value
-    /// for color 0.
+    /// Writes the color palette to a [`std::io::Write`]. Color 0 is written as
+    /// the last entry, see [`Palette::read`].
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
         for color in &self.colors[1..] {
             color.write(&mut writer)?;
         }
+        self.colors[0].write(&mut writer)?;
 
         Ok(())
     }
@@ -308,6 +539,14 @@ impl<'a> Iterator for PaletteIter<'a> {
     }
 }
 
+impl Index<ColorIndex> for MaterialPalette {
+    type Output = Material;
+
+    fn index(&self, material_id: ColorIndex) -> &Self::Output {
+        &self.materials[&material_id]
+    }
+}
+
 impl Index<ColorIndex> for Palette {
     type Output = Color;
 
@@ -329,8 +568,9 @@ impl Index<ColorIndex> for Palette {
     serde(transparent)
 )]
 pub struct MaterialPalette {
-    /// TODO: Does the material ID correspond to a ColorIndex?
-    materials: HashMap<ColorIndex, Material>,
+    /// Material IDs correspond to palette entries. In files, the material for
+    /// index 0 is stored with ID 256.
+    materials: BTreeMap<ColorIndex, Material>,
 }
 
 impl MaterialPalette {
@@ -339,6 +579,52 @@ impl MaterialPalette {
         self.materials.is_empty()
     }
 
+    /// Returns the number of materials in the palette.
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    /// Sets the material for a palette entry. Returns the old material, if
+    /// there was one.
+    pub fn insert(&mut self, material_id: ColorIndex, material: Material) -> Option<Material> {
+        self.materials.insert(material_id, material)
+    }
+
+    /// Removes the material for a palette entry and returns it.
+    pub fn remove(&mut self, material_id: ColorIndex) -> Option<Material> {
+        self.materials.remove(&material_id)
+    }
+
+    /// Sorts the attributes of all materials by key.
+    pub fn sort_attributes(&mut self) {
+        for material in self.materials.values_mut() {
+            material.attributes.sort();
+        }
+    }
+
+    /// Reads a material with its ID from a [`std::io::Read`]. This reads the
+    /// content of a `MATL` chunk.
+    pub fn read_material<R: Read>(mut reader: R) -> Result<(ColorIndex, Material), ReadError> {
+        // Material 256 is the material for palette index 0.
+        let material_id = ColorIndex((reader.read_u32::<LE>()? & 0xff) as u8);
+        Ok((material_id, Material::read(reader)?))
+    }
+
+    /// Writes a material with its ID to a [`std::io::Write`]. This writes the
+    /// content of a `MATL` chunk.
+    pub fn write_material<W: Write>(
+        mut writer: W,
+        material_id: ColorIndex,
+        material: &Material,
+    ) -> Result<(), WriteError> {
+        let material_id = match material_id.0 {
+            0 => 256,
+            id => u32::from(id),
+        };
+        writer.write_u32::<LE>(material_id)?;
+        material.write(writer)
+    }
+
     /// Returns the  material with ID `material_id` from the palette. Returns
     /// `None`, if there is no material with this ID. This is equivalent to
     /// `material_palette[material_id]`.
@@ -359,7 +645,7 @@ impl MaterialPalette {
     ///
     /// This interface his likely to change in the future and is not fully
     /// implemented yet.
-    pub fn iter(&self) -> MaterialPaletteIter {
+    pub fn iter(&self) -> MaterialPaletteIter<'_> {
         MaterialPaletteIter {
             inner: self.materials.iter(),
         }
@@ -370,7 +656,7 @@ impl MaterialPalette {
 /// [`MaterialPalette::iter`].
 #[derive(Debug)]
 pub struct MaterialPaletteIter<'a> {
-    inner: std::collections::hash_map::Iter<'a, ColorIndex, Material>,
+    inner: std::collections::btree_map::Iter<'a, ColorIndex, Material>,
 }
 
 impl<'a> Iterator for MaterialPaletteIter<'a> {
@@ -501,81 +787,106 @@ impl fmt::Display for ColorIndex {
     }
 }
 
-/// A material definition.
+/// A material definition, as stored in a `MATL` chunk.
+///
+/// MagicaVoxel stores materials as a dictionary of properties, such as
+/// `_type`, `_rough` or `_ior`. These are kept as [`Attributes`], so
+/// properties this crate doesn't know about are preserved. There are getters
+/// for the common properties.
 ///
 /// # Work-in-Progress
 ///
 /// This interface his likely to change in the future and is not fully
 /// implemented yet.
-#[derive(Clone, Debug)]
-#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(Serialize, Deserialize),
+    serde(transparent)
+)]
 pub struct Material {
-    /// The type of material.
-    pub ty: MaterialType,
-
-    /// The mateiral weight. This has a different meaning depending on the
-    /// material type:
-    ///  - [`MaterialType::Diffuse`]: Always `1.0`.
-    ///  - [`MaterialType::Metal`]: Blends between metal and diffuse material.
-    ///    Must be in interval `(0.0, 1.0]`.
-    ///  - [`MaterialType::Glass`]: Blends between glass and diffuse material.
-    ///    Must be in interval `(0.0, 1.0]`.
-    ///  - [`MaterialType::Emissive`]: The intensity of emitted light. Must be
-    ///    in interval `(0.0, 1.0]`.
-    pub weight: f32,
-
-    pub plastic: Option<f32>,
-    pub roughness: Option<f32>,
-    pub specular: Option<f32>,
-    pub ior: Option<f32>,
-    pub attenuation: Option<f32>,
-    pub power: Option<f32>,
-    pub glow: Option<f32>,
-    pub is_total_power: bool,
+    pub attributes: Attributes,
 }
 
 impl Material {
-    /// Reads a material definition from a [`std::io::Read`].
-    pub fn read<R: Read>(mut reader: R) -> Result<Self, ReadError> {
-        let ty = MaterialType::read(&mut reader)?;
-        let weight = reader.read_f32::<LE>()?;
-        let flags = reader.read_u32::<LE>()?;
-
-        let plastic = (flags & 1 != 0)
-            .then(|| reader.read_f32::<LE>())
-            .transpose()?;
-        let roughness = (flags & 2 != 0)
-            .then(|| reader.read_f32::<LE>())
-            .transpose()?;
-        let specular = (flags & 4 != 0)
-            .then(|| reader.read_f32::<LE>())
-            .transpose()?;
-        let ior = (flags & 8 != 0)
-            .then(|| reader.read_f32::<LE>())
-            .transpose()?;
-        let attenuation = (flags & 16 != 0)
-            .then(|| reader.read_f32::<LE>())
-            .transpose()?;
-        let power = (flags & 32 != 0)
-            .then(|| reader.read_f32::<LE>())
-            .transpose()?;
-        let glow = (flags & 64 != 0)
-            .then(|| reader.read_f32::<LE>())
-            .transpose()?;
-
-        Ok(Material {
-            ty,
-            weight,
-            plastic,
-            roughness,
-            specular,
-            ior,
-            attenuation,
-            power,
-            glow,
-            is_total_power: (flags & 128 != 0),
+    /// Creates a material of the given type without any other properties.
+    pub fn new(ty: MaterialType) -> Self {
+        let mut material = Self::default();
+        material.attributes.insert("_type", ty.as_str());
+        material
+    }
+
+    /// Reads a material definition from a [`std::io::Read`]. This reads the
+    /// content of a `MATL` chunk, after the material ID.
+    pub fn read<R: Read>(reader: R) -> Result<Self, ReadError> {
+        Ok(Self {
+            attributes: Attributes::read(reader)?,
         })
     }
+
+    /// Writes the material definition to a [`std::io::Write`]. This writes the
+    /// content of a `MATL` chunk, after the material ID.
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), WriteError> {
+        self.attributes.write(writer)
+    }
+
+    /// Returns the type of material (`_type` property), or `None` if it's
+    /// missing or unknown.
+    pub fn ty(&self) -> Option<MaterialType> {
+        self.attributes.get("_type")?.parse().ok()
+    }
+
+    /// Returns a property as `f32`, or `None` if it's missing or not a
+    /// number.
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.attributes.get(key)?.parse().ok()
+    }
+
+    /// Sets a property to a `f32` value.
+    pub fn set_f32(&mut self, key: &str, value: f32) {
+        self.attributes.insert(key, value.to_string());
+    }
+
+    /// The material weight (`_weight`). This blends between the material and
+    /// a diffuse material.
+    pub fn weight(&self) -> Option<f32> {
+        self.get_f32("_weight")
+    }
+
+    /// The roughness (`_rough`).
+    pub fn roughness(&self) -> Option<f32> {
+        self.get_f32("_rough")
+    }
+
+    /// The specular reflection (`_spec`).
+    pub fn specular(&self) -> Option<f32> {
+        self.get_f32("_spec")
+    }
+
+    /// The metalness (`_metal`).
+    pub fn metalness(&self) -> Option<f32> {
+        self.get_f32("_metal")
+    }
+
+    /// The index of refraction (`_ior`).
+    pub fn ior(&self) -> Option<f32> {
+        self.get_f32("_ior")
+    }
+
+    /// The attenuation of glass and media (`_att`).
+    pub fn attenuation(&self) -> Option<f32> {
+        self.get_f32("_att")
+    }
+
+    /// The emission of emissive materials (`_emit`).
+    pub fn emission(&self) -> Option<f32> {
+        self.get_f32("_emit")
+    }
+
+    /// The radiant flux of emissive materials (`_flux`).
+    pub fn flux(&self) -> Option<f32> {
+        self.get_f32("_flux")
+    }
 }
 
 /// A material type.
@@ -586,13 +897,59 @@ impl Material {
 /// implemented yet.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum MaterialType {
     Diffuse,
     Metal,
     Glass,
     Emissive,
+    Blend,
+    Media,
+}
+
+impl MaterialType {
+    /// Returns the value of the `_type` property for this material type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MaterialType::Diffuse => "_diffuse",
+            MaterialType::Metal => "_metal",
+            MaterialType::Glass => "_glass",
+            MaterialType::Emissive => "_emit",
+            MaterialType::Blend => "_blend",
+            MaterialType::Media => "_media",
+        }
+    }
 }
 
+impl FromStr for MaterialType {
+    type Err = MaterialTypeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "_diffuse" => Ok(MaterialType::Diffuse),
+            "_metal" => Ok(MaterialType::Metal),
+            "_glass" => Ok(MaterialType::Glass),
+            "_emit" => Ok(MaterialType::Emissive),
+            "_blend" => Ok(MaterialType::Blend),
+            "_media" => Ok(MaterialType::Media),
+            _ => Err(MaterialTypeParseError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for MaterialType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid material type: {0}")]
+pub struct MaterialTypeParseError(pub String);
+
+/// Numeric material types, as stored in the legacy `MATT` chunk. Only
+/// [`MaterialType::Diffuse`], [`MaterialType::Metal`], [`MaterialType::Glass`]
+/// and [`MaterialType::Emissive`] have a numeric type.
 impl TryFrom<u8> for MaterialType {
     type Error = MaterialTryFromError;
 
@@ -607,19 +964,24 @@ impl TryFrom<u8> for MaterialType {
     }
 }
 
-impl From<MaterialType> for u8 {
-    fn from(ty: MaterialType) -> Self {
+impl TryFrom<MaterialType> for u8 {
+    type Error = MaterialTryIntoError;
+
+    fn try_from(ty: MaterialType) -> Result<Self, Self::Error> {
         match ty {
-            MaterialType::Diffuse => 0,
-            MaterialType::Metal => 1,
-            MaterialType::Glass => 2,
-            MaterialType::Emissive => 3,
+            MaterialType::Diffuse => Ok(0),
+            MaterialType::Metal => Ok(1),
+            MaterialType::Glass => Ok(2),
+            MaterialType::Emissive => Ok(3),
+            ty => Err(MaterialTryIntoError(ty)),
         }
     }
 }
 
 impl MaterialType {
-    /// Reads a material type from a [`std::io::Read`].
+    /// Reads a numeric material type from a [`std::io::Read`].
+    #[deprecated(note = "`MATL` chunks store the material type as a string. Use `Material::ty`.")]
+    #[allow(deprecated)]
     pub fn read<R: Read>(mut reader: R) -> Result<Self, ReadError> {
         reader
             .read_u8()?
@@ -627,22 +989,33 @@ impl MaterialType {
             .map_err(|e: MaterialTryFromError| ReadError::InvalidMaterial { material_type: e.0 })
     }
 
+    /// Writes a numeric material type to a [`std::io::Write`]. Fails for
+    /// material types without a numeric type.
+    #[deprecated(note = "`MATL` chunks store the material type as a string. Use `Material::new`.")]
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
-        writer.write_u8((*self).into())?;
+        let ty = u8::try_from(*self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        writer.write_u8(ty)?;
         Ok(())
     }
 }
 
+/// Error returned when converting an invalid numeric material type.
+#[derive(Debug, Error)]
+#[error("Invalid material type: {0}")]
+pub struct MaterialTryFromError(pub u8);
+
+/// Error returned when converting a material type without a numeric type.
+#[derive(Debug, Error)]
+#[error("Material type has no numeric type: {0}")]
+pub struct MaterialTryIntoError(pub MaterialType);
+
 /// A transform node.
 ///
 /// # Work-in-Progress
 ///
 /// This interface his likely to change in the future and is not fully
 /// implemented yet.
-#[derive(Debug, Error)]
-#[error("Invalid material type: {0}")]
-pub struct MaterialTryFromError(pub u8);
-
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Transform {
@@ -679,6 +1052,22 @@ impl Transform {
         })
     }
 
+    /// Writes the transform node to a [`std::io::Write`].
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
+        writer.write_u32::<LE>(self.node_id)?;
+        self.attributes.write(&mut writer)?;
+        writer.write_u32::<LE>(self.child_node_id)?;
+        write_id_opt(&mut writer, self.reserved_id)?;
+        write_id_opt(&mut writer, self.layer_id)?;
+
+        writer.write_u32::<LE>(self.frames.len().try_into()?)?;
+        for frame in &self.frames {
+            frame.write(&mut writer)?;
+        }
+
+        Ok(())
+    }
+
     pub fn get_transform(&self, frame: usize) -> Option<Vector<i32>> {
         let mut parts = self.frames.get(frame)?.get("_t")?.split_whitespace();
         let x = parts.next()?.parse().ok()?;
@@ -687,6 +1076,12 @@ impl Transform {
 
         parts.next().is_none().then(|| Vector::new(x, y, z))
     }
+
+    /// Returns the rotation (`_r` attribute) of the given frame, or `None` if
+    /// the frame has no (valid) rotation.
+    pub fn get_rotation(&self, frame: usize) -> Option<Rotation> {
+        Rotation::from_byte(self.frames.get(frame)?.get("_r")?.parse().ok()?)
+    }
 }
 
 /// A group node.
@@ -721,6 +1116,19 @@ impl Group {
             children,
         })
     }
+
+    /// Writes the group node to a [`std::io::Write`].
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
+        writer.write_u32::<LE>(self.node_id)?;
+        self.attributes.write(&mut writer)?;
+
+        writer.write_u32::<LE>(self.children.len().try_into()?)?;
+        for child in &self.children {
+            writer.write_u32::<LE>(*child)?;
+        }
+
+        Ok(())
+    }
 }
 
 /// A shape node.
@@ -734,16 +1142,66 @@ impl Group {
 pub struct Shape {
     pub node_id: u32,
     pub attributes: Attributes,
+    pub models: Vec<ShapeModel>,
 }
 
 impl Shape {
     /// Reads a shape node from a [`std::io::Read`].
     pub fn read<R: Read>(mut reader: R) -> Result<Self, ReadError> {
+        let node_id = reader.read_u32::<LE>()?;
+        let attributes = Attributes::read(&mut reader)?;
+        // The number of models is read from the file, so we don't preallocate.
+        let num_models = reader.read_u32::<LE>()?;
+        let mut models = vec![];
+
+        for _ in 0..num_models {
+            models.push(ShapeModel::read(&mut reader)?);
+        }
+
         Ok(Self {
-            node_id: reader.read_u32::<LE>()?,
+            node_id,
+            attributes,
+            models,
+        })
+    }
+
+    /// Writes the shape node to a [`std::io::Write`].
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
+        writer.write_u32::<LE>(self.node_id)?;
+        self.attributes.write(&mut writer)?;
+
+        writer.write_u32::<LE>(self.models.len().try_into()?)?;
+        for model in &self.models {
+            model.write(&mut writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A reference from a [`Shape`] to a model.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ShapeModel {
+    /// The index of the model in the file.
+    pub model_id: u32,
+    pub attributes: Attributes,
+}
+
+impl ShapeModel {
+    /// Reads a model reference from a [`std::io::Read`].
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, ReadError> {
+        Ok(Self {
+            model_id: reader.read_u32::<LE>()?,
             attributes: Attributes::read(reader)?,
         })
     }
+
+    /// Writes the model reference to a [`std::io::Write`].
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
+        writer.write_u32::<LE>(self.model_id)?;
+        self.attributes.write(writer)
+    }
 }
 
 /// A layer node.
@@ -769,37 +1227,45 @@ impl Layer {
             reserved_id: read_id_opt(reader)?,
         })
     }
+
+    /// Writes the layer node to a [`std::io::Write`].
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
+        writer.write_u32::<LE>(self.node_id)?;
+        self.attributes.write(&mut writer)?;
+        write_id_opt(writer, self.reserved_id)
+    }
 }
 
 /// Node attributes. These contain meta-data for nodes, such as [`Transform`] or
 /// [`Layer`].
-#[derive(Clone, Debug, Default)]
-#[cfg_attr(
-    feature = "serialize",
-    derive(Serialize, Deserialize),
-    serde(transparent)
-)]
+///
+/// Attributes keep the order in which they were inserted (or read), so they're
+/// written in the same order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Attributes {
-    inner: HashMap<String, String>,
+    inner: Vec<(String, String)>,
 }
 
 impl Attributes {
-    /// Reads attributes from a [`std::io::Read`].
+    /// Reads attributes from a [`std::io::Read`]. Unlike [`Self::insert`]
+    /// this doesn't merge duplicate keys.
     pub fn read<R: Read>(mut reader: R) -> Result<Self, ReadError> {
         // An array of key value pairs, where key and value are strings prefixed with
         // length as u32
 
-        let mut inner = HashMap::new();
+        let mut attributes = Attributes::default();
         let num_items = reader.read_u32::<LE>()?;
         log::trace!("Attributes::read: num_items={}", num_items);
         for _ in 0..num_items {
             let key = Self::read_string(&mut reader)?;
             let value = Self::read_string(&mut reader)?;
             log::trace!("Attributes::read: key={}, value={}", key, value);
-            inner.insert(key, value);
+            // Pairs are kept as they are, including duplicate keys, so they're
+            // written back unchanged.
+            attributes.inner.push((key, value));
         }
 
-        Ok(Attributes { inner })
+        Ok(attributes)
     }
 
     fn read_string<R: Read>(mut reader: R) -> Result<String, ReadError> {
@@ -811,25 +1277,138 @@ impl Attributes {
         Ok(String::from_utf8(buf)?)
     }
 
+    /// Writes attributes to a [`std::io::Write`].
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), WriteError> {
+        writer.write_u32::<LE>(self.inner.len().try_into()?)?;
+        for (key, value) in self.iter() {
+            Self::write_string(&mut writer, key)?;
+            Self::write_string(&mut writer, value)?;
+        }
+        Ok(())
+    }
+
+    fn write_string<W: Write>(mut writer: W, s: &str) -> Result<(), WriteError> {
+        writer.write_u32::<LE>(s.len().try_into()?)?;
+        writer.write_all(s.as_bytes())?;
+        Ok(())
+    }
+
+    /// Inserts an attribute. If the attribute already exists, its value is
+    /// replaced, but it keeps its position. Returns the old value, if there
+    /// was one.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let key = key.into();
+        let value = value.into();
+
+        if let Some((_, old_value)) = self.inner.iter_mut().find(|(k, _)| *k == key) {
+            Some(std::mem::replace(old_value, value))
+        }
+        else {
+            self.inner.push((key, value));
+            None
+        }
+    }
+
+    /// Removes an attribute. Returns its value, if there was one.
+    pub fn remove(&mut self, key: impl AsRef<str>) -> Option<String> {
+        let key = key.as_ref();
+        let index = self.inner.iter().position(|(k, _)| k == key)?;
+        Some(self.inner.remove(index).1)
+    }
+
+    /// Tests if there are no attributes.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns the number of attributes.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
     /// Returns the attribute with the given key, or `None`, if no such
     /// attribute exists.
     pub fn get(&self, key: impl AsRef<str>) -> Option<&str> {
-        Some(self.inner.get(key.as_ref())?.as_str())
+        let key = key.as_ref();
+        self.inner
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the name (`_name` attribute).
+    pub fn name(&self) -> Option<&str> {
+        self.get("_name")
+    }
+
+    /// Returns whether the `_hidden` attribute is set.
+    pub fn is_hidden(&self) -> bool {
+        self.get("_hidden") == Some("1")
+    }
+
+    /// Sorts the attributes by key.
+    pub fn sort(&mut self) {
+        self.inner.sort_by(|(a, _), (b, _)| a.cmp(b));
     }
 
-    /// Creates an iterator over the attributes. The iterator returns items
-    /// `(&str, &str)`.
-    pub fn iter(&self) -> AttributesIter {
+    /// Creates an iterator over the attributes in insertion order. The
+    /// iterator returns items `(&str, &str)`.
+    pub fn iter(&self) -> AttributesIter<'_> {
         AttributesIter {
             inner: self.inner.iter(),
         }
     }
 }
 
+impl FromIterator<(String, String)> for Attributes {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        let mut attributes = Self::default();
+        for (key, value) in iter {
+            attributes.insert(key, value);
+        }
+        attributes
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl Serialize for Attributes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'de> Deserialize<'de> for Attributes {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AttributesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for AttributesVisitor {
+            type Value = Attributes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of attributes")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Attributes, A::Error> {
+                let mut attributes = Attributes::default();
+                while let Some((key, value)) = map.next_entry::<String, String>()? {
+                    attributes.insert(key, value);
+                }
+                Ok(attributes)
+            }
+        }
+
+        deserializer.deserialize_map(AttributesVisitor)
+    }
+}
+
 /// An interator over attributes. Created with [`Attributes::iter`].
 #[derive(Debug)]
 pub struct AttributesIter<'a> {
-    inner: std::collections::hash_map::Iter<'a, String, String>,
+    inner: std::slice::Iter<'a, (String, String)>,
 }
 
 impl<'a> Iterator for AttributesIter<'a> {
@@ -839,8 +1418,26 @@ impl<'a> Iterator for AttributesIter<'a> {
         let (key, value) = self.inner.next()?;
         Some((key.as_ref(), value.as_ref()))
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for AttributesIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.inner.next_back()?;
+        Some((key.as_ref(), value.as_ref()))
+    }
 }
 
+impl<'a> ExactSizeIterator for AttributesIter<'a> {}
+
 fn read_id_opt<R: Read>(mut reader: R) -> Result<Option<u32>, ReadError> {
     Ok(reader.read_i32::<LE>()?.try_into().ok())
 }
+
+fn write_id_opt<W: Write>(mut writer: W, id: Option<u32>) -> Result<(), WriteError> {
+    writer.write_i32::<LE>(id.map(i32::try_from).transpose()?.unwrap_or(-1))?;
+    Ok(())
+}
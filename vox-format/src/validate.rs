@@ -0,0 +1,255 @@
+//! Validation of voxel data before it is written.
+//!
+//! The writer will write anything it's given, but MagicaVoxel rejects or
+//! misrenders files with e.g. voxels outside of their model. [`validate`]
+//! checks a [`VoxSource`] for these problems. [`crate::writer::WriteOptions`]
+//! can be used to run it before writing.
+
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    fmt,
+};
+
+use thiserror::Error;
+
+use crate::{
+    data::{
+        VoxModelSource,
+        VoxModels,
+        VoxSource,
+    },
+    scene::SceneError,
+    types::{
+        Model,
+        Point,
+        Size,
+        Voxel,
+    },
+};
+
+/// The maximum size of a model along each axis that MagicaVoxel supports.
+pub const MAX_MODEL_SIZE: u32 = 256;
+
+/// Displays the first of a list of errors, and how many more there are. This is
+/// used for errors that wrap a list of problems.
+pub(crate) struct ErrorList<'a, E>(pub &'a [E]);
+
+impl<E: fmt::Display> fmt::Display for ErrorList<'_, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            [] => write!(f, "no errors"),
+            [error] => write!(f, "{}", error),
+            [error, rest @ ..] => write!(f, "{} (and {} more)", error, rest.len()),
+        }
+    }
+}
+
+/// A problem with a single model. These are returned by [`Model::validate`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Error)]
+pub enum ModelError {
+    /// The model is empty along an axis, or larger than [`MAX_MODEL_SIZE`].
+    #[error("Invalid model size: {size:?}")]
+    InvalidSize { size: Size },
+
+    /// A voxel lies outside of the model's size.
+    #[error("Voxel at {:?} is out of bounds", .voxel.point)]
+    OutOfBounds { voxel: Voxel },
+
+    /// Multiple voxels have the same point.
+    #[error("Duplicate voxel at {point:?}")]
+    DuplicateVoxel { point: Point },
+
+    /// A voxel has color index 0, which MagicaVoxel uses for empty space.
+    #[error("Voxel at {point:?} has color index 0")]
+    EmptyColorIndex { point: Point },
+
+    /// The model has more voxels than can be stored in the file.
+    #[error("Too many voxels: {num_voxels}")]
+    TooManyVoxels { num_voxels: usize },
+}
+
+/// A problem found by [`validate`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Error)]
+pub enum ValidationError {
+    /// A model is invalid.
+    #[error("Model {model_id}: {error}")]
+    Model { model_id: usize, error: ModelError },
+
+    /// The scene graph is invalid.
+    #[error(transparent)]
+    Scene(#[from] SceneError),
+}
+
+/// Validates all models and the scene graph of `source`. Returns an empty
+/// `Vec` if it's valid.
+pub fn validate<S: VoxSource + ?Sized>(source: &S) -> Vec<ValidationError> {
+    let num_models = source.num_models();
+
+    let mut errors = vec![];
+
+    for model_id in 0..num_models {
+        errors.extend(
+            validate_model(source.model_size(model_id), source.voxels(model_id))
+                .into_iter()
+                .map(|error| ValidationError::Model { model_id, error }),
+        );
+    }
+
+    if let Some(scene) = source.scene() {
+        errors.extend(
+            scene
+                .validate(num_models)
+                .into_iter()
+                .map(ValidationError::Scene),
+        );
+    }
+
+    errors
+}
+
+/// Validates a single model given by its size and voxels.
+pub fn validate_model<I: IntoIterator<Item = Voxel>>(size: Size, voxels: I) -> Vec<ModelError> {
+    let mut errors = vec![];
+
+    let is_valid_size = |n: u32| (1..=MAX_MODEL_SIZE).contains(&n);
+    if !(is_valid_size(size.x) && is_valid_size(size.y) && is_valid_size(size.z)) {
+        errors.push(ModelError::InvalidSize { size });
+    }
+
+    // Points are stored as unsigned bytes.
+    let in_bounds = |p: i8, n: u32| u32::from(p as u8) < n;
+
+    let mut points = HashSet::new();
+    let mut num_voxels = 0usize;
+
+    for voxel in voxels {
+        let point = voxel.point;
+
+        if !(in_bounds(point.x, size.x) && in_bounds(point.y, size.y) && in_bounds(point.z, size.z))
+        {
+            errors.push(ModelError::OutOfBounds { voxel });
+        }
+
+        if !points.insert(point) {
+            errors.push(ModelError::DuplicateVoxel { point });
+        }
+
+        if voxel.color_index.0 == 0 {
+            errors.push(ModelError::EmptyColorIndex { point });
+        }
+
+        num_voxels += 1;
+    }
+
+    if u32::try_from(num_voxels).is_err() {
+        errors.push(ModelError::TooManyVoxels { num_voxels });
+    }
+
+    errors
+}
+
+impl Model {
+    /// Validates this model. Returns an empty `Vec` if it's valid.
+    pub fn validate(&self) -> Vec<ModelError> {
+        validate_model(self.size, self.voxels.iter().copied())
+    }
+}
+
+impl<V: VoxModelSource> VoxModels<V> {
+    /// Validates all models and the scene graph. Returns an empty `Vec` if
+    /// it's valid. See [`validate`].
+    pub fn validate(&self) -> Vec<ValidationError> {
+        validate(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ModelError,
+        ValidationError,
+    };
+    use crate::{
+        data::VoxData,
+        scene::{
+            SceneBuilder,
+            SceneError,
+            SceneTransform,
+            ROOT_NODE_ID,
+        },
+        types::{
+            Model,
+            Vector,
+            Voxel,
+        },
+    };
+
+    #[test]
+    fn it_validates_models() {
+        let model = Model {
+            size: Vector::new(2, 2, 300),
+            voxels: vec![
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([2, 0, 0], 1),
+                Voxel::new([0, 0, 0], 2),
+                Voxel::new([1, 1, 1], 0),
+            ],
+        };
+
+        assert_eq!(
+            model.validate(),
+            vec![
+                ModelError::InvalidSize {
+                    size: Vector::new(2, 2, 300)
+                },
+                ModelError::OutOfBounds {
+                    voxel: Voxel::new([2, 0, 0], 1)
+                },
+                ModelError::DuplicateVoxel {
+                    point: Vector::new(0, 0, 0)
+                },
+                ModelError::EmptyColorIndex {
+                    point: Vector::new(1, 1, 1)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_accepts_points_above_127() {
+        let model = Model {
+            size: Vector::new(256, 1, 1),
+            voxels: vec![Voxel::new([-1, 0, 0], 1)],
+        };
+        assert!(model.validate().is_empty());
+    }
+
+    #[test]
+    fn it_validates_vox_data() {
+        let mut vox = VoxData::default();
+        vox.models.push(Model {
+            size: Vector::new(1, 1, 1),
+            voxels: vec![Voxel::new([0, 1, 0], 1)],
+        });
+        let mut builder = SceneBuilder::new();
+        builder.add_instance(ROOT_NODE_ID, 1, SceneTransform::identity());
+        vox.scene = builder.build();
+
+        let errors = vox.validate();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0],
+            ValidationError::Model {
+                model_id: 0,
+                error: ModelError::OutOfBounds {
+                    voxel: Voxel::new([0, 1, 0], 1)
+                }
+            }
+        );
+        assert!(matches!(
+            errors[1],
+            ValidationError::Scene(SceneError::MissingModel { model_id: 1, .. })
+        ));
+    }
+}
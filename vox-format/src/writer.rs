@@ -1,30 +1,83 @@
 //! Provides functions to write VOX files. This is work-in-progress.
 
 use std::{
+    borrow::Cow,
+    collections::{
+        HashMap,
+        HashSet,
+    },
     convert::TryInto,
-    fs::OpenOptions,
+    ffi::OsString,
+    fs::{
+        self,
+        File,
+        OpenOptions,
+    },
     io::{
-        Cursor,
+        BufWriter,
+        ErrorKind,
         Seek,
+        SeekFrom,
         Write,
     },
-    path::Path,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    },
 };
 
 use byteorder::{
     WriteBytesExt,
     LE,
 };
+#[cfg(feature = "serialize")]
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use thiserror::Error;
 
 use crate::{
     chunk::{
         chunk_writer,
+        write_chunk,
+        write_chunk_header,
         ChunkId,
         ChunkWriter,
+        LengthCounter,
+    },
+    data::VoxSource,
+    layout::{
+        Layout,
+        LayoutEntry,
+        RawChunk,
+    },
+    scene::{
+        Node,
+        Scene,
+        SceneError,
+    },
+    types::{
+        ColorIndex,
+        Layer,
+        Material,
+        MaterialPalette,
+        Palette,
+        Point,
+        Size,
+        Version,
+        VersionPolicy,
+        Voxel,
+    },
+    validate::{
+        validate,
+        ErrorList,
+        ValidationError,
     },
-    data::VoxData,
-    types::Version,
 };
 
 /// Error type returned when writing fails.
@@ -37,12 +90,111 @@ pub enum Error {
     #[error("Integer overflow")]
     Overflow(#[from] std::num::TryFromIntError),
 
+    /// The scene graph is invalid. See [`crate::scene::Scene::validate`].
+    #[error("Invalid scene graph: {}", ErrorList(.0))]
+    InvalidScene(Vec<SceneError>),
+
+    /// The data is invalid. This is only returned if
+    /// [`WriteOptions::strict`] is set. See [`crate::validate::validate`].
+    #[error("Invalid data: {}", ErrorList(.0))]
+    InvalidData(Vec<ValidationError>),
+
+    /// The version to write is not accepted by
+    /// [`WriteOptions::version_policy`].
+    #[error("Unsupported file version: {version}")]
+    UnsupportedFileVersion { version: Version },
+
+    /// A [`VoxSource`] yielded a different number of voxels for a model than
+    /// it reported with [`VoxSource::num_voxels`].
+    #[error("Model {model_index} has {actual} voxels, but {expected} were reported")]
+    VoxelCountMismatch {
+        model_index: usize,
+        expected: usize,
+        actual: usize,
+    },
+
     /// This is a work-around,since sometimes we want to read VOX files in a
     /// chunk-writer closure.
     #[error("Reader error")]
     Reader(#[from] crate::reader::Error),
 }
 
+/// Options for [`to_writer_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// Validate the data with [`crate::validate::validate`] before writing. If
+    /// it's invalid, [`Error::InvalidData`] is returned and nothing is
+    /// written.
+    pub strict: bool,
+
+    /// Write canonical output, so that equal content always results in the
+    /// same bytes. Voxels are sorted in the given order, the scene graph is
+    /// brought into canonical form with [`Scene::canonicalize`], and all
+    /// attributes are sorted.
+    pub canonical: Option<VoxelOrder>,
+
+    /// Write chunks in the order of the source's [`Layout`], including the
+    /// `PACK` and `RGBA` chunks if they're in the layout, and any raw chunks.
+    /// Together with [`crate::reader::ReadOptions::record_layout`] this writes
+    /// unmodified files byte-exact. This is ignored in canonical mode.
+    pub preserve_layout: bool,
+
+    /// The version written to the file header. If `None`, the source's
+    /// version is used.
+    pub version: Option<Version>,
+
+    /// Which versions may be written. By default only [`Version::KNOWN`]
+    /// versions are written, and anything else results in
+    /// [`Error::UnsupportedFileVersion`].
+    pub version_policy: VersionPolicy,
+}
+
+/// The order in which voxels are written in canonical mode. Voxels at the
+/// same point are sorted by color index.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum VoxelOrder {
+    /// Sorted by x, then y, then z coordinate.
+    Lexicographic,
+
+    /// Sorted along the Z-order curve, which keeps voxels that are close to
+    /// each other close in the file.
+    Morton,
+}
+
+impl VoxelOrder {
+    /// Sorts `voxels` in this order.
+    pub fn sort(&self, voxels: &mut [Voxel]) {
+        match self {
+            VoxelOrder::Lexicographic => {
+                voxels.sort_by_key(|voxel| {
+                    let point = voxel.point;
+                    (
+                        point.x as u8,
+                        point.y as u8,
+                        point.z as u8,
+                        voxel.color_index,
+                    )
+                })
+            }
+            VoxelOrder::Morton => {
+                voxels.sort_by_key(|voxel| (morton_code(voxel.point), voxel.color_index))
+            }
+        }
+    }
+}
+
+/// Interleaves the bits of the coordinates of `point`.
+fn morton_code(point: Point) -> u32 {
+    let mut code = 0;
+    for bit in 0..8 {
+        for (axis, coordinate) in [point.x, point.y, point.z].iter().enumerate() {
+            code |= u32::from(*coordinate as u8 >> bit & 1) << (3 * bit + axis);
+        }
+    }
+    code
+}
+
 /// Writes the file header for a VOX file.
 fn write_file_header<W: Write>(mut writer: W, version: Version) -> Result<(), Error> {
     writer.write_all(b"VOX ")?;
@@ -62,58 +214,1472 @@ pub fn main_chunk_writer<W: Write + Seek, F: FnMut(&mut ChunkWriter<W>) -> Resul
     chunk_writer(writer, ChunkId::Main, f)
 }
 
-/// Writes [`crate::data::VoxData`] to a [`std::io::Write`].
-pub fn to_writer<W: Write + Seek>(writer: W, vox: &VoxData) -> Result<(), Error> {
-    main_chunk_writer(writer, Version::default(), |chunk_writer| {
-        // Write PACK, if there is more than 1 model.
-        // FIXME: Apparently PACK is not used anymore.
-        if vox.models.len() > 1 {
-            chunk_writer.child_content_writer(ChunkId::Pack, |writer| {
-                writer.write_u32::<LE>(vox.models.len().try_into()?)?;
+/// Writes the `MAIN` chunk including the file signature to a writer that
+/// doesn't need to implement `Seek`. See [`write_chunk`] for how the closure is
+/// used.
+pub fn write_main_chunk<W: Write, F: FnMut(&mut dyn Write) -> Result<(), Error>>(
+    mut writer: W,
+    version: Version,
+    children: F,
+) -> Result<(), Error> {
+    write_file_header(&mut writer, version)?;
+
+    write_chunk(writer, ChunkId::Main, |_| Ok(()), children)
+}
+
+/// Writes a [`VoxSource`], e.g. [`crate::data::VoxData`], to a
+/// [`std::io::Write`].
+///
+/// This doesn't need to seek, so you can write to e.g. `stdout` or a socket.
+///
+/// Returns [`Error::InvalidScene`] if the scene graph is invalid.
+pub fn to_writer<W: Write, S: VoxSource + ?Sized>(writer: W, source: &S) -> Result<(), Error> {
+    to_writer_with_options(writer, source, &WriteOptions::default())
+}
+
+/// Same as [`to_writer`], but lets you specify [`WriteOptions`].
+pub fn to_writer_with_options<W: Write, S: VoxSource + ?Sized>(
+    mut writer: W,
+    source: &S,
+    options: &WriteOptions,
+) -> Result<(), Error> {
+    with_children(source, options, |version, children| {
+        write_file_header(&mut writer, version)?;
+        write_chunk_header(&mut writer, ChunkId::Main, 0, children_len(children)?)?;
+
+        for child in children {
+            child.write(&mut writer, source, options)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Checks the version and validates `source` according to `options`. Then
+/// calls `f` with the version and the children of the `MAIN` chunk, in the
+/// order in which they're written.
+fn with_children<S: VoxSource + ?Sized, R>(
+    source: &S,
+    options: &WriteOptions,
+    f: impl FnOnce(Version, &[SizedChunk]) -> Result<R, Error>,
+) -> Result<R, Error> {
+    let version = options.version.unwrap_or_else(|| source.version());
+    if !version.is_accepted_by(options.version_policy) {
+        return Err(Error::UnsupportedFileVersion { version });
+    }
+
+    if options.strict {
+        let errors = validate(source);
+        if !errors.is_empty() {
+            return Err(Error::InvalidData(errors));
+        }
+    }
+    else if let Some(scene) = source.scene() {
+        let errors = scene.validate(source.num_models());
+        if !errors.is_empty() {
+            return Err(Error::InvalidScene(errors));
+        }
+    }
+
+    let scene = source.scene().map(|scene| {
+        if options.canonical.is_some() {
+            let mut scene = scene.clone();
+            scene.canonicalize();
+            Cow::Owned(scene)
+        }
+        else {
+            Cow::Borrowed(scene)
+        }
+    });
+
+    let materials = source.materials().map(|materials| {
+        if options.canonical.is_some() {
+            let mut materials = materials.clone();
+            materials.sort_attributes();
+            Cow::Owned(materials)
+        }
+        else {
+            Cow::Borrowed(materials)
+        }
+    });
+
+    let layout = source
+        .layout()
+        .filter(|_| options.preserve_layout && options.canonical.is_none());
+
+    let children = children(source, scene.as_deref(), materials.as_deref(), layout)
+        .into_iter()
+        .map(|chunk| SizedChunk::new(chunk, source))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    f(version, &children)
+}
+
+/// Returns the children of the `MAIN` chunk in the order in which they're
+/// written. `scene` and `materials` are written instead of the source's.
+///
+/// If there is a `layout`, chunks are written in that order first. Anything
+/// that is not in the layout is written afterwards in the default order.
+fn children<'a, S: VoxSource + ?Sized>(
+    source: &'a S,
+    scene: Option<&'a Scene>,
+    materials: Option<&'a MaterialPalette>,
+    layout: Option<&'a Layout>,
+) -> Vec<ChildChunk<'a>> {
+    let num_models = source.num_models();
+    let nodes = scene.map_or(&[][..], |scene| &scene.nodes);
+    let layers = scene.map_or(&[][..], |scene| &scene.layers);
+
+    // PACK is only used by legacy files without a scene graph.
+    let write_pack = nodes.is_empty() && num_models > 1;
+
+    let mut children = vec![];
+
+    // Keep track of what was written already, when following the layout.
+    let mut sizes_written = vec![false; num_models];
+    let mut voxels_written = vec![false; num_models];
+    let mut nodes_written = vec![false; nodes.len()];
+    let mut layers_written = vec![false; layers.len()];
+    let mut palette_written = false;
+    let mut materials_written = HashSet::new();
+
+    // Layout entries reference nodes and layers by ID.
+    let node_indices = index_by_id(nodes.iter().map(Node::node_id));
+    let layer_indices = index_by_id(layers.iter().map(|layer| layer.node_id));
+
+    for entry in layout.iter().flat_map(|layout| &layout.entries) {
+        match entry {
+            LayoutEntry::Pack => {
+                if write_pack {
+                    children.push(SmallChunk::Pack(num_models).into());
+                }
+            }
+            LayoutEntry::Size(i) => {
+                if mark_written(&mut sizes_written, *i) {
+                    children.push(SmallChunk::Size(source.model_size(*i)).into());
+                }
+            }
+            LayoutEntry::Xyzi(i) => {
+                if mark_written(&mut voxels_written, *i) {
+                    children.push(ChildChunk::Xyzi(*i));
+                }
+            }
+            LayoutEntry::Node(node_id) => {
+                if let Some(i) = node_indices.get(node_id) {
+                    if mark_written(&mut nodes_written, *i) {
+                        children.push(SmallChunk::Node(&nodes[*i]).into());
+                    }
+                }
+            }
+            LayoutEntry::Layer(layer_id) => {
+                if let Some(i) = layer_indices.get(layer_id) {
+                    if mark_written(&mut layers_written, *i) {
+                        children.push(SmallChunk::Layer(&layers[*i]).into());
+                    }
+                }
+            }
+            LayoutEntry::Rgba => {
+                if !palette_written {
+                    children.push(SmallChunk::Rgba(source.palette()).into());
+                    palette_written = true;
+                }
+            }
+            LayoutEntry::Material(material_id) => {
+                if let Some(material) = materials.and_then(|materials| materials.get(*material_id))
+                {
+                    if materials_written.insert(*material_id) {
+                        children.push(SmallChunk::Material(*material_id, material).into());
+                    }
+                }
+            }
+            LayoutEntry::Raw(chunk) => children.push(ChildChunk::Raw(chunk)),
+        }
+    }
+
+    // Write PACK, if there is more than 1 model and no scene graph. If the layout
+    // is preserved, PACK is only written if the file had one.
+    if layout.is_none() && write_pack {
+        children.push(SmallChunk::Pack(num_models).into());
+    }
+
+    // Write models
+    for model_index in 0..num_models {
+        if mark_written(&mut sizes_written, model_index) {
+            children.push(SmallChunk::Size(source.model_size(model_index)).into());
+        }
+        if mark_written(&mut voxels_written, model_index) {
+            children.push(ChildChunk::Xyzi(model_index));
+        }
+    }
+
+    // Write scene graph
+    for (i, node) in nodes.iter().enumerate() {
+        if mark_written(&mut nodes_written, i) {
+            children.push(SmallChunk::Node(node).into());
+        }
+    }
+    for (i, layer) in layers.iter().enumerate() {
+        if mark_written(&mut layers_written, i) {
+            children.push(SmallChunk::Layer(layer).into());
+        }
+    }
+
+    // Write palette
+    if !palette_written && !source.palette().is_default() {
+        children.push(SmallChunk::Rgba(source.palette()).into());
+    }
+
+    // Write materials
+    for (material_id, material) in materials.iter().flat_map(|materials| materials.iter()) {
+        if !materials_written.contains(&material_id) {
+            children.push(SmallChunk::Material(material_id, material).into());
+        }
+    }
+
+    children
+}
+
+/// Maps IDs to the index of the first item with that ID.
+fn index_by_id(ids: impl Iterator<Item = u32>) -> HashMap<u32, usize> {
+    let mut indices = HashMap::new();
+    for (i, id) in ids.enumerate() {
+        indices.entry(id).or_insert(i);
+    }
+    indices
+}
+
+/// Sets `written[i]` and returns `true`, if it wasn't set already. Returns
+/// `false`, if `i` is out of bounds.
+fn mark_written(written: &mut [bool], i: usize) -> bool {
+    match written.get_mut(i) {
+        Some(written) if !*written => {
+            *written = true;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Returns the total length of `children`, including their headers.
+fn children_len(children: &[SizedChunk]) -> Result<u32, Error> {
+    Ok(children
+        .iter()
+        .map(SizedChunk::len)
+        .sum::<u64>()
+        .try_into()?)
+}
+
+/// A child of the `MAIN` chunk.
+enum ChildChunk<'a> {
+    /// The `XYZI` chunk of the model with this index. Its length is computed
+    /// from the number of voxels, so the voxels are only encoded once.
+    Xyzi(usize),
+
+    /// A chunk from the [`Layout`] that is written as-is.
+    Raw(&'a RawChunk),
+
+    Small(SmallChunk<'a>),
+}
+
+impl<'a> From<SmallChunk<'a>> for ChildChunk<'a> {
+    fn from(chunk: SmallChunk<'a>) -> Self {
+        ChildChunk::Small(chunk)
+    }
+}
+
+/// A [`ChildChunk`] with its lengths.
+struct SizedChunk<'a> {
+    chunk: ChildChunk<'a>,
+    content_len: u32,
+    children_len: u32,
+}
+
+impl<'a> SizedChunk<'a> {
+    fn new<S: VoxSource + ?Sized>(chunk: ChildChunk<'a>, source: &S) -> Result<Self, Error> {
+        let (content_len, children_len) = match &chunk {
+            ChildChunk::Xyzi(model_index) => {
+                // The number of voxels, followed by 4 bytes per voxel.
+                let num_voxels = source.num_voxels(*model_index) as u64;
+                ((4 + 4 * num_voxels).try_into()?, 0)
+            }
+            ChildChunk::Raw(chunk) => {
+                (
+                    chunk.content.len().try_into()?,
+                    chunk.children.len().try_into()?,
+                )
+            }
+            ChildChunk::Small(chunk) => (chunk.content_len()?, 0),
+        };
+
+        Ok(Self {
+            chunk,
+            content_len,
+            children_len,
+        })
+    }
+
+    /// Returns the length of the chunk including its header.
+    fn len(&self) -> u64 {
+        12 + u64::from(self.content_len) + u64::from(self.children_len)
+    }
+
+    fn write<S: VoxSource + ?Sized>(
+        &self,
+        writer: &mut dyn Write,
+        source: &S,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        let chunk_id = match &self.chunk {
+            ChildChunk::Xyzi(_) => ChunkId::Xyzi,
+            ChildChunk::Raw(chunk) => chunk.id,
+            ChildChunk::Small(chunk) => chunk.id(),
+        };
+        write_chunk_header(&mut *writer, chunk_id, self.content_len, self.children_len)?;
+
+        match &self.chunk {
+            ChildChunk::Xyzi(model_index) => write_voxels(writer, source, *model_index, options),
+            ChildChunk::Raw(chunk) => {
+                writer.write_all(&chunk.content)?;
+                writer.write_all(&chunk.children)?;
                 Ok(())
-            })?;
+            }
+            ChildChunk::Small(chunk) => chunk.write_content(writer),
         }
+    }
+}
+
+/// Writes the content of an `XYZI` chunk. In canonical mode the voxels are
+/// sorted.
+fn write_voxels<S: VoxSource + ?Sized>(
+    writer: &mut dyn Write,
+    source: &S,
+    model_index: usize,
+    options: &WriteOptions,
+) -> Result<(), Error> {
+    // The chunk length was computed from `num_voxels`, so the source must
+    // yield exactly that many voxels.
+    let expected = source.num_voxels(model_index);
+    let mismatch = |actual| {
+        Error::VoxelCountMismatch {
+            model_index,
+            expected,
+            actual,
+        }
+    };
+
+    writer.write_u32::<LE>(expected.try_into()?)?;
 
-        // Write models
-        for model in &vox.models {
-            // Write SIZE chunk
-            chunk_writer.child_content_writer(ChunkId::Size, |writer| {
-                model.size.write(writer)?;
+    if let Some(order) = options.canonical {
+        let mut voxels = source.voxels(model_index).collect::<Vec<_>>();
+        if voxels.len() != expected {
+            return Err(mismatch(voxels.len()));
+        }
+        order.sort(&mut voxels);
+        for voxel in &voxels {
+            voxel.write(&mut *writer)?;
+        }
+    }
+    else {
+        let mut actual = 0;
+        for voxel in source.voxels(model_index) {
+            actual += 1;
+            if actual > expected {
+                // Count the rest, but don't write past the end of the chunk.
+                continue;
+            }
+            voxel.write(&mut *writer)?;
+        }
+        if actual != expected {
+            return Err(mismatch(actual));
+        }
+    }
+
+    Ok(())
+}
+
+/// A chunk with small content, that is encoded twice: once to count its length
+/// and once to write it.
+enum SmallChunk<'a> {
+    Pack(usize),
+    Size(Size),
+    Node(&'a Node),
+    Layer(&'a Layer),
+    Rgba(&'a Palette),
+    Material(ColorIndex, &'a Material),
+}
+
+impl SmallChunk<'_> {
+    fn id(&self) -> ChunkId {
+        match self {
+            SmallChunk::Pack(_) => ChunkId::Pack,
+            SmallChunk::Size(_) => ChunkId::Size,
+            SmallChunk::Node(Node::Transform(_)) => ChunkId::NTrn,
+            SmallChunk::Node(Node::Group(_)) => ChunkId::NGrp,
+            SmallChunk::Node(Node::Shape(_)) => ChunkId::NShp,
+            SmallChunk::Layer(_) => ChunkId::Layr,
+            SmallChunk::Rgba(_) => ChunkId::Rgba,
+            SmallChunk::Material(..) => ChunkId::Matl,
+        }
+    }
+
+    fn content_len(&self) -> Result<u32, Error> {
+        LengthCounter::count(|writer| self.write_content(writer))
+    }
+
+    fn write_content(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        match self {
+            SmallChunk::Pack(num_models) => {
+                writer.write_u32::<LE>((*num_models).try_into()?)?;
                 Ok(())
-            })?;
+            }
+            SmallChunk::Size(size) => size.write(writer),
+            SmallChunk::Node(Node::Transform(transform)) => transform.write(writer),
+            SmallChunk::Node(Node::Group(group)) => group.write(writer),
+            SmallChunk::Node(Node::Shape(shape)) => shape.write(writer),
+            SmallChunk::Layer(layer) => layer.write(writer),
+            SmallChunk::Rgba(palette) => palette.write(writer),
+            SmallChunk::Material(material_id, material) => {
+                MaterialPalette::write_material(writer, *material_id, material)
+            }
+        }
+    }
+
+    /// Writes the chunk including its header.
+    fn write(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        write_chunk_header(&mut *writer, self.id(), self.content_len()?, 0)?;
+        self.write_content(writer)
+    }
+}
+
+/// Writes a VOX file incrementally.
+///
+/// Models are written as soon as they're added with [`VoxWriter::add_model`],
+/// so they never need to be in memory at once. The palette, materials and
+/// scene graph are small, so they're kept until [`VoxWriter::finish`] writes
+/// them and fixes up the length of the `MAIN` chunk. If the `VoxWriter` is
+/// dropped without calling `finish`, the file is incomplete.
+///
+/// Since the number of models isn't known in advance, no `PACK` chunk is
+/// written.
+///
+/// Many small writes are issued, so you should wrap the writer in a
+/// [`std::io::BufWriter`].
+///
+/// # Example
+///
+/// ```
+/// # use std::io::Cursor;
+/// # use vox_format::{types::{Size, Voxel}, writer::VoxWriter};
+/// let mut writer = VoxWriter::new(Cursor::new(vec![])).unwrap();
+/// for i in 0..4 {
+///     let voxels = (0..i).map(|x| Voxel::new([x, 0, 0], 1));
+///     writer.add_model(Size::new(4, 1, 1), voxels).unwrap();
+/// }
+/// let data = writer.finish().unwrap().into_inner();
+///
+/// let vox = vox_format::from_slice(&data).unwrap();
+/// assert_eq!(vox.models.len(), 4);
+/// ```
+#[derive(Debug)]
+pub struct VoxWriter<W> {
+    writer: W,
+
+    /// Offset of the `MAIN` chunk's header.
+    main_offset: u64,
 
-            // Write XYZI chunk
-            chunk_writer.child_content_writer(ChunkId::Xyzi, |mut writer| {
-                writer.write_u32::<LE>(model.voxels.len().try_into()?)?;
-                for voxel in &model.voxels {
-                    voxel.write(&mut writer)?;
+    num_models: usize,
+    palette: Palette,
+    materials: MaterialPalette,
+    scene: Scene,
+}
+
+impl<W: Write + Seek> VoxWriter<W> {
+    /// Creates a `VoxWriter` and writes the file header with the default
+    /// version.
+    pub fn new(writer: W) -> Result<Self, Error> {
+        Self::with_version(writer, Version::default())
+    }
+
+    /// Creates a `VoxWriter` and writes the file header with the given
+    /// version.
+    ///
+    /// Returns [`Error::UnsupportedFileVersion`] and writes nothing, if the
+    /// version is not one of [`Version::KNOWN`]. Use
+    /// [`VoxWriter::with_version_policy`] to write other versions.
+    pub fn with_version(writer: W, version: Version) -> Result<Self, Error> {
+        Self::with_version_policy(writer, version, VersionPolicy::default())
+    }
+
+    /// Creates a `VoxWriter` and writes the file header with the given
+    /// version, if it's accepted by `version_policy`. Otherwise
+    /// [`Error::UnsupportedFileVersion`] is returned and nothing is written.
+    pub fn with_version_policy(
+        mut writer: W,
+        version: Version,
+        version_policy: VersionPolicy,
+    ) -> Result<Self, Error> {
+        if !version.is_accepted_by(version_policy) {
+            return Err(Error::UnsupportedFileVersion { version });
+        }
+
+        write_file_header(&mut writer, version)?;
+
+        let main_offset = writer.stream_position()?;
+        ChunkId::Main.write(&mut writer)?;
+        // `content_len` and `children_len` are written by `finish`.
+        writer.write_all(&[0; 8])?;
+
+        Ok(Self {
+            writer,
+            main_offset,
+            num_models: 0,
+            palette: Palette::default(),
+            materials: MaterialPalette::default(),
+            scene: Scene::default(),
+        })
+    }
+
+    /// Returns the number of models added so far.
+    pub fn num_models(&self) -> usize {
+        self.num_models
+    }
+
+    /// Writes a model and returns its model ID. `voxels` is only iterated
+    /// once.
+    pub fn add_model<I: IntoIterator<Item = Voxel>>(
+        &mut self,
+        size: Size,
+        voxels: I,
+    ) -> Result<u32, Error> {
+        let model_id = self.num_models.try_into()?;
+
+        chunk_writer(&mut self.writer, ChunkId::Size, |chunk_writer| {
+            chunk_writer.content_writer(|writer| size.write(writer))
+        })?;
+
+        let mut voxels = voxels.into_iter();
+        chunk_writer(&mut self.writer, ChunkId::Xyzi, |chunk_writer| {
+            chunk_writer.content_writer(|writer| {
+                // The number of voxels is written after all voxels are known.
+                writer.write_u32::<LE>(0)?;
+
+                let mut num_voxels = 0usize;
+                for voxel in &mut voxels {
+                    voxel.write(&mut *writer)?;
+                    num_voxels += 1;
                 }
+
+                writer.seek(SeekFrom::Start(0))?;
+                writer.write_u32::<LE>(num_voxels.try_into()?)?;
+                writer.seek(SeekFrom::End(0))?;
+
                 Ok(())
-            })?;
+            })
+        })?;
+
+        self.num_models += 1;
+
+        Ok(model_id)
+    }
+
+    /// Sets the color palette.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Adds a material for the color `color_index`.
+    pub fn add_material(&mut self, color_index: ColorIndex, material: Material) {
+        self.materials.insert(color_index, material);
+    }
+
+    /// Adds a node to the scene graph. The scene graph is validated by
+    /// [`VoxWriter::finish`].
+    pub fn add_node(&mut self, node: Node) {
+        self.scene.nodes.push(node);
+    }
+
+    /// Adds a layer to the scene graph.
+    pub fn add_layer(&mut self, layer: Layer) {
+        self.scene.layers.push(layer);
+    }
+
+    /// Sets the scene graph, replacing any nodes and layers added before. This
+    /// is useful with [`crate::scene::SceneBuilder`].
+    pub fn set_scene(&mut self, scene: Scene) {
+        self.scene = scene;
+    }
+
+    /// Writes the scene graph, palette and materials, and fixes up the
+    /// `MAIN` chunk header. Returns the underlying writer.
+    ///
+    /// Returns [`Error::InvalidScene`] if the scene graph is invalid.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let errors = self.scene.validate(self.num_models);
+        if !errors.is_empty() {
+            return Err(Error::InvalidScene(errors));
         }
 
-        // Write palette
-        if !vox.palette.is_default() {
-            chunk_writer.child_content_writer(ChunkId::Rgba, |writer| {
-                vox.palette.write(writer)?;
-                Ok(())
-            })?;
+        let nodes = self.scene.nodes.iter().map(SmallChunk::Node);
+        let layers = self.scene.layers.iter().map(SmallChunk::Layer);
+        let palette = Some(SmallChunk::Rgba(&self.palette)).filter(|_| !self.palette.is_default());
+        let materials = self
+            .materials
+            .iter()
+            .map(|(material_id, material)| SmallChunk::Material(material_id, material));
+        for chunk in nodes.chain(layers).chain(palette).chain(materials) {
+            chunk.write(&mut self.writer)?;
         }
 
-        Ok(())
+        let end = self.writer.stream_position()?;
+        let children_len: u32 = (end - self.main_offset - 12).try_into()?;
+
+        self.writer.seek(SeekFrom::Start(self.main_offset + 8))?;
+        self.writer.write_u32::<LE>(children_len)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// Returns the number of bytes [`to_writer`] would write for `source`. This
+/// doesn't encode any voxels.
+pub fn encoded_len<S: VoxSource + ?Sized>(source: &S) -> Result<usize, Error> {
+    with_children(source, &WriteOptions::default(), |_, children| {
+        // File header and `MAIN` chunk header.
+        Ok((8 + 12 + u64::from(children_len(children)?)).try_into()?)
     })
 }
 
-/// Encode [`VoxData`] and return bytes as `Vec<u8>`.
-pub fn to_vec(vox: &VoxData) -> Result<Vec<u8>, Error> {
-    //let mut buf = Vec::with_capacity(vox.size_hint());
-    let mut buf = Vec::with_capacity(1024);
-    to_writer(Cursor::new(&mut buf), vox)?;
-    buf.shrink_to_fit();
+/// Encode a [`VoxSource`] and return bytes as `Vec<u8>`.
+pub fn to_vec<S: VoxSource + ?Sized>(source: &S) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(encoded_len(source)?);
+    to_writer(&mut buf, source)?;
     Ok(buf)
 }
 
-/// Writes VOX data to the specified path.
-pub fn to_file<P: AsRef<Path>>(path: P, vox: &VoxData) -> Result<(), Error> {
-    to_writer(OpenOptions::new().create(true).write(true).open(path)?, vox)
+/// Writes VOX data to the specified path. The file is replaced atomically,
+/// see [`write_file_atomic`].
+pub fn to_file<P: AsRef<Path>, S: VoxSource + ?Sized>(path: P, source: &S) -> Result<(), Error> {
+    to_file_with_options(
+        path,
+        source,
+        &WriteOptions::default(),
+        &FileOptions::default(),
+    )
+}
+
+/// Same as [`to_file`], but lets you specify [`WriteOptions`] and
+/// [`FileOptions`].
+pub fn to_file_with_options<P: AsRef<Path>, S: VoxSource + ?Sized>(
+    path: P,
+    source: &S,
+    options: &WriteOptions,
+    file_options: &FileOptions,
+) -> Result<(), Error> {
+    write_file_atomic(path, file_options, |file| {
+        let mut writer = BufWriter::new(file);
+        to_writer_with_options(&mut writer, source, options)?;
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Options for [`write_file_atomic`].
+#[derive(Clone, Debug, Default)]
+pub struct FileOptions {
+    /// If set, and the file already exists, it is copied to this path before
+    /// it's replaced.
+    pub backup: Option<PathBuf>,
+}
+
+/// Used to generate unique names for temporary files.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a file atomically.
+///
+/// The closure writes to a temporary file in the same directory as `path`.
+/// Afterwards the temporary file is synced to disk and renamed to `path`. So
+/// `path` either contains the old or the new file, even if writing fails or the
+/// process crashes. If the closure fails, the temporary file is removed and
+/// the original file is left untouched.
+///
+/// # Example
+///
+/// ```no_run
+/// # use vox_format::{types::{Size, Voxel}, writer::{write_file_atomic, VoxWriter}};
+/// write_file_atomic("huge.vox", &Default::default(), |file| {
+///     let mut writer = VoxWriter::new(file)?;
+///     writer.add_model(Size::new(1, 1, 1), vec![Voxel::new([0, 0, 0], 1)])?;
+///     writer.finish()?;
+///     Ok(())
+/// })
+/// .unwrap();
+/// ```
+pub fn write_file_atomic<P: AsRef<Path>, F: FnOnce(&mut File) -> Result<(), Error>>(
+    path: P,
+    options: &FileOptions,
+    f: F,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "Path has no file name"))?;
+
+    let (temp_path, mut file) = loop {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp_path = dir.join(temp_name);
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => break (temp_path, file),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    log::trace!("Writing to temporary file: {}", temp_path.display());
+
+    let result = (|| {
+        // Keep the permissions of the file we replace.
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+
+        f(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+
+        if let Some(backup) = &options.backup {
+            if path.exists() {
+                log::debug!("Backing up {} to {}", path.display(), backup.display());
+                fs::copy(path, backup)?;
+            }
+        }
+
+        fs::rename(&temp_path, path)?;
+
+        // Sync the directory, so that the rename is persisted.
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+
+        Ok(())
+    })();
+
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        fs,
+        io::{
+            Cursor,
+            Write,
+        },
+        path::PathBuf,
+    };
+
+    use super::{
+        encoded_len,
+        to_file_with_options,
+        to_vec,
+        to_writer,
+        to_writer_with_options,
+        write_file_atomic,
+        Error,
+        FileOptions,
+        VoxWriter,
+        VoxelOrder,
+        WriteOptions,
+    };
+    use crate::{
+        data::{
+            VoxData,
+            VoxSource,
+        },
+        reader::{
+            from_slice,
+            from_slice_with_options,
+            ReadOptions,
+        },
+        scene::{
+            Node,
+            SceneBuilder,
+            SceneError,
+            SceneTransform,
+            ROOT_NODE_ID,
+        },
+        types::{
+            Attributes,
+            Color,
+            Material,
+            MaterialType,
+            Model,
+            Palette,
+            Size,
+            Vector,
+            Version,
+            VersionPolicy,
+            Voxel,
+        },
+        validate::ValidationError,
+    };
+
+    #[test]
+    fn it_writes_materials_layers_and_names() {
+        let mut vox = VoxData::default();
+        vox.models.push(Model {
+            size: Vector::new(1, 1, 1),
+            voxels: vec![Voxel::new([0, 0, 0], 1)],
+        });
+
+        let mut glass = Material::new(MaterialType::Glass);
+        glass.set_f32("_ior", 1.5);
+        glass.set_f32("_alpha", 0.25);
+        vox.materials.insert(1.into(), glass);
+        vox.materials
+            .insert(0.into(), Material::new(MaterialType::Diffuse));
+
+        let mut builder = SceneBuilder::new();
+        let layer = builder.add_layer("windows");
+        let window = builder.add_instance(ROOT_NODE_ID, 0, SceneTransform::identity());
+        builder.set_name(window, "window");
+        builder.set_layer(window, layer);
+        vox.scene = builder.build();
+
+        let data = to_vec(&vox).unwrap();
+        assert_eq!(data.len(), encoded_len(&vox).unwrap());
+        assert_eq!(data.capacity(), data.len());
+
+        let vox = from_slice(&data).unwrap();
+
+        assert_eq!(vox.materials.len(), 2);
+        let glass = vox.materials.get(1.into()).unwrap();
+        assert_eq!(glass.ty(), Some(MaterialType::Glass));
+        assert_eq!(glass.ior(), Some(1.5));
+        assert_eq!(glass.get_f32("_alpha"), Some(0.25));
+        assert_eq!(
+            vox.materials.get(0.into()).unwrap().ty(),
+            Some(MaterialType::Diffuse)
+        );
+
+        assert_eq!(vox.scene.layers[0].attributes.name(), Some("windows"));
+        let nodes = vox.scene.find_by_name("window");
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(
+            vox.scene.layer_members(layer)[0].node_id,
+            nodes[0].node_id()
+        );
+    }
+
+    /// A writer that can't seek.
+    struct Stream(Vec<u8>);
+
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_writes_to_non_seekable_writers() {
+        let vox = from_slice(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_multiple_models.vox"
+        )))
+        .unwrap();
+
+        let mut stream = Stream(vec![]);
+        to_writer(&mut stream, &vox).unwrap();
+
+        assert_eq!(stream.0.len(), encoded_len(&vox).unwrap());
+        assert_eq!(stream.0, to_vec(&vox).unwrap());
+
+        let read_back = from_slice(&stream.0).unwrap();
+        assert_eq!(read_back.models.len(), vox.models.len());
+        for (a, b) in read_back.models.iter().zip(&vox.models) {
+            assert_eq!(a.size, b.size);
+            assert_eq!(a.voxels, b.voxels);
+        }
+    }
+
+    /// A source that generates a line of voxels along the x axis.
+    struct Line {
+        len: i8,
+        palette: Palette,
+
+        /// Number of calls to `voxels`.
+        iterations: Cell<usize>,
+    }
+
+    impl VoxSource for Line {
+        type Voxels<'a> = Box<dyn Iterator<Item = Voxel> + 'a>;
+
+        fn palette(&self) -> &Palette {
+            &self.palette
+        }
+
+        fn num_models(&self) -> usize {
+            1
+        }
+
+        fn model_size(&self, _model_index: usize) -> Size {
+            Vector::new(self.len as u32, 1, 1)
+        }
+
+        fn num_voxels(&self, _model_index: usize) -> usize {
+            self.len as usize
+        }
+
+        fn voxels(&self, _model_index: usize) -> Self::Voxels<'_> {
+            self.iterations.set(self.iterations.get() + 1);
+            Box::new((0..self.len).map(|x| Voxel::new([x, 0, 0], x as u8 + 1)))
+        }
+    }
+
+    #[test]
+    fn it_writes_custom_sources() {
+        let line = Line {
+            len: 5,
+            palette: Palette::default(),
+            iterations: Cell::new(0),
+        };
+
+        let vox = from_slice(&to_vec(&line).unwrap()).unwrap();
+
+        assert_eq!(vox.models.len(), 1);
+        assert_eq!(vox.models[0].size, Vector::new(5, 1, 1));
+        assert_eq!(vox.models[0].voxels, line.voxels(0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_encodes_voxels_once() {
+        let line = Line {
+            len: 5,
+            palette: Palette::default(),
+            iterations: Cell::new(0),
+        };
+
+        let data = to_vec(&line).unwrap();
+        assert_eq!(line.iterations.get(), 1);
+        assert_eq!(data.len(), 8 + 12 + 12 + 12 + 12 + 4 + 4 * 5);
+    }
+
+    /// A [`Line`] that reports the wrong number of voxels.
+    struct MiscountedLine {
+        line: Line,
+        num_voxels: usize,
+    }
+
+    impl VoxSource for MiscountedLine {
+        type Voxels<'a> = Box<dyn Iterator<Item = Voxel> + 'a>;
+
+        fn palette(&self) -> &Palette {
+            self.line.palette()
+        }
+
+        fn num_models(&self) -> usize {
+            self.line.num_models()
+        }
+
+        fn model_size(&self, model_index: usize) -> Size {
+            self.line.model_size(model_index)
+        }
+
+        fn num_voxels(&self, _model_index: usize) -> usize {
+            self.num_voxels
+        }
+
+        fn voxels(&self, model_index: usize) -> Self::Voxels<'_> {
+            self.line.voxels(model_index)
+        }
+    }
+
+    #[test]
+    fn it_rejects_miscounted_voxels() {
+        for num_voxels in [3, 7] {
+            for canonical in [None, Some(VoxelOrder::Lexicographic)] {
+                let source = MiscountedLine {
+                    line: Line {
+                        len: 5,
+                        palette: Palette::default(),
+                        iterations: Cell::new(0),
+                    },
+                    num_voxels,
+                };
+                let options = WriteOptions {
+                    canonical,
+                    ..Default::default()
+                };
+
+                let mut data = vec![];
+                let result = to_writer_with_options(&mut data, &source, &options);
+                assert!(
+                    matches!(
+                        result,
+                        Err(Error::VoxelCountMismatch {
+                            model_index: 0,
+                            expected,
+                            actual: 5,
+                        }) if expected == num_voxels
+                    ),
+                    "{:?}",
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn it_writes_incrementally() {
+        let mut vox = VoxData::default();
+        vox.models.push(Model {
+            size: Vector::new(2, 2, 2),
+            voxels: vec![Voxel::new([0, 0, 0], 1), Voxel::new([1, 1, 1], 2)],
+        });
+        vox.palette.colors[1].r = 42;
+        vox.materials
+            .insert(1.into(), Material::new(MaterialType::Metal));
+        let mut builder = SceneBuilder::new();
+        builder.add_instance(ROOT_NODE_ID, 0, SceneTransform::identity());
+        vox.scene = builder.build();
+
+        let mut writer = VoxWriter::new(Cursor::new(vec![])).unwrap();
+        let model = &vox.models[0];
+        writer
+            .add_model(model.size, model.voxels.iter().copied())
+            .unwrap();
+        writer.set_palette(vox.palette.clone());
+        writer.add_material(1.into(), Material::new(MaterialType::Metal));
+        writer.set_scene(vox.scene.clone());
+        let data = writer.finish().unwrap().into_inner();
+
+        assert_eq!(data, to_vec(&vox).unwrap());
+    }
+
+    #[test]
+    fn it_refuses_unsupported_versions_when_streaming() {
+        let mut buf = vec![];
+        assert!(matches!(
+            VoxWriter::with_version(Cursor::new(&mut buf), Version(42)),
+            Err(Error::UnsupportedFileVersion {
+                version: Version(42)
+            })
+        ));
+        assert!(buf.is_empty());
+
+        let writer = VoxWriter::with_version_policy(
+            Cursor::new(&mut buf),
+            Version(42),
+            VersionPolicy::Permissive,
+        )
+        .unwrap();
+        writer.finish().unwrap();
+        assert_eq!(&buf[4..8], &42u32.to_le_bytes());
+    }
+
+    #[test]
+    fn it_refuses_invalid_scenes_when_finishing() {
+        let mut builder = SceneBuilder::new();
+        builder.add_instance(ROOT_NODE_ID, 0, SceneTransform::identity());
+
+        let mut writer = VoxWriter::new(Cursor::new(vec![])).unwrap();
+        writer.set_scene(builder.build());
+
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn it_refuses_invalid_data_in_strict_mode() {
+        let mut vox = VoxData::default();
+        vox.models.push(Model {
+            size: Vector::new(1, 1, 1),
+            voxels: vec![Voxel::new([0, 0, 0], 0)],
+        });
+
+        let options = WriteOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        assert!(matches!(
+            to_writer_with_options(&mut buf, &vox, &options),
+            Err(Error::InvalidData(_))
+        ));
+        assert!(buf.is_empty());
+
+        to_writer(&mut buf, &vox).unwrap();
+    }
+
+    #[test]
+    fn it_displays_lists_of_invalid_data() {
+        let error = |n| {
+            Error::InvalidData(vec![ValidationError::Scene(SceneError::MissingRoot); n]).to_string()
+        };
+        assert_eq!(error(0), "Invalid data: no errors");
+        assert_eq!(
+            error(1),
+            format!("Invalid data: {}", SceneError::MissingRoot)
+        );
+        assert!(error(3).ends_with(" (and 2 more)"));
+    }
+
+    #[test]
+    fn it_keeps_attribute_order() {
+        let mut attributes = Attributes::default();
+        attributes.insert("b", "1");
+        attributes.insert("a", "2");
+        attributes.insert("b", "3");
+
+        let mut buf = vec![];
+        attributes.write(&mut buf).unwrap();
+        let attributes = Attributes::read(&buf[..]).unwrap();
+
+        assert_eq!(
+            attributes.iter().collect::<Vec<_>>(),
+            vec![("b", "3"), ("a", "2")]
+        );
+    }
+
+    #[test]
+    fn it_keeps_duplicate_attributes() {
+        let mut buf = vec![];
+        buf.extend_from_slice(&3u32.to_le_bytes());
+        for (key, value) in [("b", "1"), ("a", "2"), ("b", "3")] {
+            for s in [key, value] {
+                buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                buf.extend_from_slice(s.as_bytes());
+            }
+        }
+
+        let attributes = Attributes::read(&buf[..]).unwrap();
+        assert_eq!(
+            attributes.iter().collect::<Vec<_>>(),
+            vec![("b", "1"), ("a", "2"), ("b", "3")]
+        );
+
+        let mut written = vec![];
+        attributes.write(&mut written).unwrap();
+        assert_eq!(written, buf);
+    }
+
+    #[test]
+    fn it_writes_canonical_output() {
+        let mut builder = SceneBuilder::new();
+        let a = builder.add_instance(ROOT_NODE_ID, 0, SceneTransform::identity());
+        builder.set_name(a, "a");
+        builder.set_hidden(a, true);
+        builder.add_instance(ROOT_NODE_ID, 0, SceneTransform::identity());
+
+        let mut vox = VoxData::default();
+        vox.models.push(Model {
+            size: Vector::new(2, 2, 2),
+            voxels: vec![
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([1, 0, 0], 2),
+                Voxel::new([0, 1, 1], 3),
+            ],
+        });
+        vox.scene = builder.build();
+
+        // The same content with voxels, nodes and attributes in a different order,
+        // and different node IDs.
+        let mut other = VoxData::default();
+        let mut model = vox.models[0].clone();
+        model.voxels.reverse();
+        other.models.push(model);
+        other.scene = vox.scene.clone();
+        let remap = |node_id: u32| if node_id == 0 { 0 } else { node_id + 100 };
+        for node in &mut other.scene.nodes {
+            match node {
+                Node::Transform(transform) => {
+                    transform.node_id = remap(transform.node_id);
+                    transform.child_node_id = remap(transform.child_node_id);
+                    transform.attributes = transform
+                        .attributes
+                        .iter()
+                        .rev()
+                        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                        .collect();
+                }
+                Node::Group(group) => {
+                    group.node_id = remap(group.node_id);
+                    group.children.iter_mut().for_each(|id| *id = remap(*id));
+                }
+                Node::Shape(shape) => shape.node_id = remap(shape.node_id),
+            }
+        }
+        other.scene.nodes.reverse();
+
+        for order in [VoxelOrder::Lexicographic, VoxelOrder::Morton] {
+            let options = WriteOptions {
+                canonical: Some(order),
+                ..Default::default()
+            };
+            let mut a = vec![];
+            to_writer_with_options(&mut a, &vox, &options).unwrap();
+            let mut b = vec![];
+            to_writer_with_options(&mut b, &other, &options).unwrap();
+            assert_eq!(a, b);
+        }
+        assert_ne!(to_vec(&vox).unwrap(), to_vec(&other).unwrap());
+
+        let options = WriteOptions {
+            canonical: Some(VoxelOrder::Morton),
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        to_writer_with_options(&mut buf, &other, &options).unwrap();
+        let read_back = from_slice(&buf).unwrap();
+        assert_eq!(
+            read_back.models[0].voxels,
+            vec![
+                Voxel::new([0, 0, 0], 1),
+                Voxel::new([1, 0, 0], 2),
+                Voxel::new([0, 1, 1], 3),
+            ]
+        );
+        assert_eq!(read_back.scene.find_by_name("a").len(), 1);
+    }
+
+    /// Options to read files with their layout.
+    fn layout_options() -> ReadOptions {
+        ReadOptions {
+            record_layout: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_writes_unmodified_files_byte_exact() {
+        let options = WriteOptions {
+            preserve_layout: true,
+            ..Default::default()
+        };
+
+        for data in [
+            &include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/ore_small.vox"
+            ))[..],
+            &include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_custom_palette.vox"
+            ))[..],
+            &include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_materials.vox"
+            ))[..],
+            &include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_multiple_models.vox"
+            ))[..],
+            &include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_no_models.vox"
+            ))[..],
+            &include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_single_model_default_palette.vox"
+            ))[..],
+        ] {
+            let vox = from_slice_with_options(data, &layout_options()).unwrap();
+            let mut buf = vec![];
+            to_writer_with_options(&mut buf, &vox, &options).unwrap();
+            assert!(buf == data);
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_last_palette_entry() {
+        let mut data = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_custom_palette.vox"
+        ))
+        .to_vec();
+
+        // The last entry of the `RGBA` chunk's content.
+        let rgba = data.windows(4).position(|id| id == b"RGBA").unwrap();
+        data[rgba + 12 + 1020..rgba + 12 + 1024].copy_from_slice(&[1, 2, 3, 4]);
+
+        let vox = from_slice_with_options(&data, &layout_options()).unwrap();
+        assert_eq!(vox.palette.colors[0], Color::new(1, 2, 3, 4));
+
+        let options = WriteOptions {
+            preserve_layout: true,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        to_writer_with_options(&mut buf, &vox, &options).unwrap();
+        assert!(buf == data);
+
+        let read_back = from_slice(&to_vec(&vox).unwrap()).unwrap();
+        assert_eq!(read_back.palette.colors[0], Color::new(1, 2, 3, 4));
+    }
+
+    #[test]
+    fn it_follows_the_layout_of_modified_scenes() {
+        let mut vox = from_slice_with_options(
+            include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_multiple_models.vox"
+            )),
+            &layout_options(),
+        )
+        .unwrap();
+
+        // Swap the two shape nodes.
+        let shapes = vox
+            .scene
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node, Node::Shape(_)))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        vox.scene.nodes.swap(shapes[0], shapes[1]);
+
+        let options = WriteOptions {
+            preserve_layout: true,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        to_writer_with_options(&mut buf, &vox, &options).unwrap();
+
+        // Nodes are written in the original order.
+        let read_back = from_slice_with_options(&buf, &layout_options()).unwrap();
+        assert_eq!(read_back.layout, vox.layout);
+        let node_ids = |vox: &VoxData| {
+            vox.scene
+                .nodes
+                .iter()
+                .map(Node::node_id)
+                .collect::<Vec<_>>()
+        };
+        vox.scene.nodes.swap(shapes[0], shapes[1]);
+        assert_eq!(node_ids(&read_back), node_ids(&vox));
+    }
+
+    #[test]
+    fn it_writes_modifications_with_preserved_layout() {
+        let mut vox = from_slice_with_options(
+            include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../test_files/test_multiple_models.vox"
+            )),
+            &layout_options(),
+        )
+        .unwrap();
+        assert!(!vox.layout.has_pack());
+        assert_eq!(vox.layout.raw_chunks().count(), 26);
+
+        vox.models[0].voxels.pop();
+        vox.models.push(Model {
+            size: Vector::new(1, 1, 1),
+            voxels: vec![Voxel::new([0, 0, 0], 1)],
+        });
+
+        let options = WriteOptions {
+            preserve_layout: true,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        to_writer_with_options(&mut buf, &vox, &options).unwrap();
+
+        let read_back = from_slice_with_options(&buf, &layout_options()).unwrap();
+        assert_eq!(read_back.models, vox.models);
+        assert_eq!(read_back.layout.raw_chunks().count(), 26);
+    }
+
+    /// Creates an empty directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vox-format-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn it_replaces_files_atomically() {
+        let dir = test_dir("atomic");
+        let path = dir.join("test.vox");
+        fs::write(&path, vec![0xff; 4096]).unwrap();
+
+        let vox = from_slice(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../test_files/test_single_model_default_palette.vox"
+        )))
+        .unwrap();
+
+        let file_options = FileOptions {
+            backup: Some(dir.join("test.vox.bak")),
+        };
+        to_file_with_options(&path, &vox, &WriteOptions::default(), &file_options).unwrap();
+
+        // The file is truncated and the backup contains the old file.
+        assert_eq!(fs::read(&path).unwrap(), to_vec(&vox).unwrap());
+        assert_eq!(
+            fs::read(dir.join("test.vox.bak")).unwrap(),
+            vec![0xff; 4096]
+        );
+
+        // A failing write leaves the file untouched and removes the temporary file.
+        let result = write_file_atomic(&path, &FileOptions::default(), |file| {
+            file.write_all(b"garbage")?;
+            Err(std::io::Error::from(std::io::ErrorKind::Other).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), to_vec(&vox).unwrap());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_writes_the_chosen_version() {
+        let vox = VoxData::default();
+
+        let mut options = WriteOptions {
+            version: Some(Version::V200),
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        to_writer_with_options(&mut buf, &vox, &options).unwrap();
+        assert_eq!(from_slice(&buf).unwrap().version, Version::V200);
+
+        options.version = Some(Version(300));
+        assert!(matches!(
+            to_writer_with_options(&mut vec![], &vox, &options),
+            Err(Error::UnsupportedFileVersion {
+                version: Version(300)
+            })
+        ));
+
+        options.version_policy = VersionPolicy::Permissive;
+        let mut buf = vec![];
+        to_writer_with_options(&mut buf, &vox, &options).unwrap();
+        assert_eq!(&buf[4..8], &300u32.to_le_bytes());
+    }
 }
@@ -1,10 +1,7 @@
 use std::{
     collections::HashSet,
     convert::TryInto,
-    fs::{
-        File,
-        OpenOptions,
-    },
+    fs::File,
     io::{
         Read,
         Write,
@@ -30,12 +27,22 @@ use vox_format::{
     },
     default_palette::DEFAULT_PALETTE,
     from_file,
+    reader::{
+        from_file_with_options,
+        ReadOptions,
+    },
     types::{
         ColorIndex,
         Model,
         Palette,
     },
-    writer::main_chunk_writer,
+    writer::{
+        main_chunk_writer,
+        to_file_with_options,
+        write_file_atomic,
+        FileOptions,
+        WriteOptions,
+    },
 };
 
 /// Tools for inspection and manipulation of MagicaVoxel VOX files.
@@ -121,6 +128,19 @@ enum Args {
         #[structopt(short = "o", long = "output")]
         output: Option<PathBuf>,
     },
+    /// Upgrades a VOX file that uses a `PACK` chunk to the scene graph layout.
+    ///
+    /// Each model is placed in the scene with a default transform. Any other
+    /// chunks are kept as they are.
+    Upgrade {
+        /// The input file that will be upgraded.
+        input: PathBuf,
+
+        /// Output file. Defaults to `INPUT.upgraded.vox` where `INPUT` is the
+        /// input file path without `.vox` file extension.
+        #[structopt(short = "o", long = "output")]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Debug)]
@@ -276,6 +296,25 @@ impl Args {
                     }
                 })?;
             }
+            Self::Upgrade { input, output } => {
+                let read_options = ReadOptions {
+                    record_layout: true,
+                    ..Default::default()
+                };
+                let mut vox = from_file_with_options(&input, &read_options)?;
+
+                if !vox.upgrade() {
+                    bail!("File already has a scene graph: {}", input.display());
+                }
+
+                let output = output.unwrap_or_else(|| default_output_path(&input, "upgraded"));
+
+                let options = WriteOptions {
+                    preserve_layout: true,
+                    ..Default::default()
+                };
+                to_file_with_options(output, &vox, &options, &FileOptions::default())?;
+            }
         }
 
         Ok(())
@@ -299,7 +338,11 @@ fn default_output_path<P: AsRef<Path>>(input: P, postfix: &str) -> PathBuf {
 fn copy_map_chunks<
     P: AsRef<Path>,
     Q: AsRef<Path>,
-    F: FnMut(&mut File, &Chunk, &mut ChunkWriter<File>) -> Result<bool, vox_format::writer::Error>,
+    F: FnMut(
+        &mut File,
+        &Chunk,
+        &mut ChunkWriter<&mut File>,
+    ) -> Result<bool, vox_format::writer::Error>,
 >(
     input: P,
     output: Q,
@@ -309,12 +352,9 @@ fn copy_map_chunks<
     let output = output.as_ref();
 
     log::debug!("Reading input: {}", input.display());
-    let mut reader = File::open(&input)?;
+    let mut reader = File::open(input)?;
     let (main_chunk, version) = read_main_chunk(&mut reader)?;
 
-    log::debug!("Writing output: {}", output.display());
-    let writer = OpenOptions::new().create(true).write(true).open(output)?;
-
     let mut chunks = vec![];
 
     for r in main_chunk.children(&mut reader) {
@@ -322,36 +362,42 @@ fn copy_map_chunks<
         chunks.push(chunk);
     }
 
-    main_chunk_writer(writer, version, |chunk_writer| {
-        let mut buf = vec![];
-
-        for chunk in &chunks {
-            if f(&mut reader, chunk, chunk_writer)? {
-                // Copy chunk
-                log::trace!("Copying chunk: {:?}", chunk.id());
-
-                buf.clear();
-                buf.reserve(chunk.content_len().try_into()?);
-
-                chunk.content(&mut reader)?.read_to_end(&mut buf)?;
-
-                chunk_writer.child_content_writer(chunk.id(), |writer| {
-                    writer.write_all(&buf)?;
-                    Ok(())
-                })?;
-
-                // TODO: If we move the copy function into `vox-format`, we can make use of the
-                // fact that we can read/write the children as a blob.
-                if chunk.children_len() != 0 {
-                    //todo!("TODO: Copy children. This is not implemented, because at this point
-                    // all supported chunk types (except `MAIN`) have no children. Please open an
-                    // issue, if you need this feature.");
-                    log::warn!("Unexpected chunk with children. Ignoring children");
+    // The output is replaced atomically, so it's also safe to write to the input
+    // file.
+    log::debug!("Writing output: {}", output.display());
+    write_file_atomic(output, &FileOptions::default(), |writer| {
+        main_chunk_writer(writer, version, |chunk_writer| {
+            let mut buf = vec![];
+
+            for chunk in &chunks {
+                if f(&mut reader, chunk, chunk_writer)? {
+                    // Copy chunk
+                    log::trace!("Copying chunk: {:?}", chunk.id());
+
+                    buf.clear();
+                    buf.reserve(chunk.content_len().try_into()?);
+
+                    chunk.content(&mut reader)?.read_to_end(&mut buf)?;
+
+                    chunk_writer.child_content_writer(chunk.id(), |writer| {
+                        writer.write_all(&buf)?;
+                        Ok(())
+                    })?;
+
+                    // TODO: If we move the copy function into `vox-format`, we can make use of the
+                    // fact that we can read/write the children as a blob.
+                    if chunk.children_len() != 0 {
+                        //todo!("TODO: Copy children. This is not implemented, because at this
+                        // point all supported chunk types (except `MAIN`)
+                        // have no children. Please open an issue, if you
+                        // need this feature.");
+                        log::warn!("Unexpected chunk with children. Ignoring children");
+                    }
                 }
             }
-        }
 
-        Ok(())
+            Ok(())
+        })
     })?;
 
     Ok(())